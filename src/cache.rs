@@ -0,0 +1,72 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::error::GaiError;
+
+/// Entries older than this are stale: the staged changes have almost certainly moved on.
+const MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Most entries kept at once; storing another prunes the oldest.
+const MAX_ENTRIES: usize = 32;
+
+/// Cache key for a request: a SHA-256 of everything that affects the reply,
+/// so it stays the same across gitai builds and Rust versions.
+pub fn key(request: &str) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, request.as_bytes());
+    digest.as_ref().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Save a reply produced by --prewarm for the next identical request.
+pub fn store(key: &str, reply: &str) -> Result<()> {
+    let path = entry_path(key)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context(GaiError::Io("Failed to create cache directory".to_string()))?;
+        prune(parent);
+    }
+
+    fs::write(&path, reply).with_context(|| GaiError::Io(format!("Failed to write cache entry {}", path.display())))
+}
+
+/// Return and remove a cached reply. Entries are used once so asking again
+/// still gets a fresh message.
+pub fn take(key: &str) -> Option<String> {
+    let path = entry_path(key).ok()?;
+    let fresh = age(&path).is_some_and(|age| age <= MAX_AGE);
+    let reply = fs::read_to_string(&path).ok();
+    let _ = fs::remove_file(&path);
+    reply.filter(|_| fresh)
+}
+
+/// Delete expired entries, then the oldest until there is room for one more.
+fn prune(dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    let mut entries: Vec<(PathBuf, Duration)> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+        .map(|path| {
+            let age = age(&path).unwrap_or(Duration::MAX);
+            (path, age)
+        })
+        .collect();
+
+    // Newest first, so everything past the cap is the oldest
+    entries.sort_by_key(|(_, age)| *age);
+    for (index, (path, age)) in entries.iter().enumerate() {
+        if *age > MAX_AGE || index + 1 >= MAX_ENTRIES {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+fn age(path: &Path) -> Option<Duration> {
+    let modified = fs::metadata(path).and_then(|meta| meta.modified()).ok()?;
+    Some(SystemTime::now().duration_since(modified).unwrap_or_default())
+}
+
+fn entry_path(key: &str) -> Result<PathBuf> {
+    let dir = crate::git(&["rev-parse", "--git-path", "gai/cache"])?;
+    Ok(PathBuf::from(dir.trim()).join(format!("{}.txt", key)))
+}
@@ -0,0 +1,317 @@
+use anyhow::{Context, Result};
+use clap::ArgMatches;
+use clap::parser::ValueSource;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::error::GaiError;
+use crate::{Args, Preset, Provider, SubjectCase};
+
+/// Settings read from config files. Every key is optional; later layers
+/// override earlier ones and command line flags override them all.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub provider: Option<Provider>,
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    /// Models users are allowed to pick, typically set by an administrator
+    pub allowed_models: Option<Vec<String>>,
+    /// Set to true to keep a log of generated messages in the git directory
+    pub history: Option<bool>,
+    /// Print the "To use this message" hint after --generate
+    pub show_hint: Option<bool>,
+    /// Template for the user message, with a `{diff}` placeholder
+    pub user_prompt: Option<String>,
+    /// Built-in system prompt style
+    pub preset: Option<Preset>,
+    /// File to read the system prompt from instead of a preset
+    pub prompt_file: Option<PathBuf>,
+    /// Shell command the generated message is piped through before use
+    pub postprocess_command: Option<String>,
+    /// Commands run in order on every message, with the diff and message as JSON on stdin;
+    /// see `run_plugins` for what they may print and how they exit
+    pub plugins: Option<Vec<String>>,
+    /// Shell command whose output is used as the diff instead of `git diff --staged`
+    pub diff_command: Option<String>,
+    /// Conventional commit types generated messages may use
+    pub allowed_types: Option<Vec<String>>,
+    /// Set to false for endpoints that reject `system` messages
+    pub system_role: Option<bool>,
+    /// Set to false to keep the branch name out of the prompt
+    pub branch_hint: Option<bool>,
+    /// Set to false to stop steering whitespace-only changes toward the `style` type
+    pub whitespace_hint: Option<bool>,
+    /// Language-specific prompt hint, or "none" to skip detection
+    pub language: Option<String>,
+    /// Capitalization of the subject's description
+    pub case: Option<SubjectCase>,
+    /// What a bare `gitai` does instead of printing the usage banner
+    pub default_action: Option<DefaultAction>,
+    /// What a bare `gitai` does when its output isn't a terminal, where the
+    /// usage banner is noise: fail (the default) or print just the message
+    pub pipe_action: Option<PipeAction>,
+    /// Glob patterns of files whose changes must never be sent to a provider
+    pub do_not_send: Option<Vec<String>>,
+    /// Whether touching a `do_not_send` file aborts (the default) or just leaves it out
+    pub do_not_send_mode: Option<DoNotSendMode>,
+    /// Regex whose matches are removed from every raw provider reply
+    #[serde(deserialize_with = "deserialize_regex")]
+    pub cleanup_regex: Option<Regex>,
+    /// Shell command that gets each message on stdin and exits non-zero to reject it
+    pub validate_command: Option<String>,
+    /// How many times a message rejected by `validate_command` is sent back for fixing
+    pub validate_retries: Option<usize>,
+    /// File of trailers such as `Signed-off-by: ...` added to every message;
+    /// blank lines and `#` comments are skipped, as in a `.gitmessage` template
+    pub trailer_template: Option<PathBuf>,
+    /// Newly added files bigger than this many KiB need --force to be committed (default 1024)
+    pub large_file_kb: Option<u64>,
+    /// Preferred first word of the description for each commit type, e.g. fix = "resolve"
+    pub type_verbs: Option<HashMap<String, String>>,
+    /// Context window in tokens per model, for models gitai doesn't know or to override its numbers
+    pub context_windows: Option<HashMap<String, usize>>,
+}
+
+/// Compile the pattern while loading, so a bad one fails before any request.
+fn deserialize_regex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Regex>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|pattern| Regex::new(&pattern).map_err(|e| serde::de::Error::custom(format!("invalid cleanup_regex: {}", e))))
+        .transpose()
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DoNotSendMode {
+    #[default]
+    Abort,
+    Exclude,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DefaultAction {
+    Commit,
+    Generate,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PipeAction {
+    Error,
+    Generate,
+}
+
+impl Config {
+    /// Load and merge every config layer, lowest precedence first:
+    /// system-wide, user, `gai.*` git config, the repository's `.gai.toml`,
+    /// then the untracked `.gai.local.toml` next to it. Each file may also be
+    /// YAML or JSON; see `CONFIG_EXTENSIONS`.
+    pub fn load() -> Result<Self> {
+        let mut merged = serde_json::Map::new();
+
+        for layer in layers() {
+            let layer = match layer {
+                Layer::File(path) => {
+                    if !path.is_file() {
+                        continue;
+                    }
+
+                    let content = fs::read_to_string(&path)
+                        .with_context(|| GaiError::Config(format!("Failed to read config file {}", path.display())))?;
+                    parse_layer(&path, &content)
+                        .with_context(|| GaiError::Config(format!("Failed to parse config file {}", path.display())))?
+                }
+                Layer::GitConfig => git_config_layer()?,
+            };
+
+            if let Value::Object(table) = layer {
+                merged.extend(table);
+            }
+        }
+
+        serde_json::from_value(Value::Object(merged)).context(GaiError::Config("Invalid configuration".to_string()))
+    }
+
+    /// Fill in every argument that was not given on the command line.
+    pub fn apply(&self, args: &mut Args, matches: &ArgMatches) -> Result<()> {
+        if let Some(provider) = self.provider
+            && !from_command_line(matches, "provider")
+        {
+            args.provider = provider;
+        }
+
+        if !from_command_line(matches, "model") {
+            args.model = match &self.model {
+                Some(model) => model.clone(),
+                None => args.provider.default_model().to_string(),
+            };
+        }
+
+        if args.temperature.is_none() {
+            args.temperature = self.temperature;
+        }
+
+        if args.user_prompt.is_none() {
+            args.user_prompt = self.user_prompt.clone();
+        }
+
+        if args.preset.is_none() {
+            args.preset = self.preset;
+        }
+
+        if args.prompt_file.is_none() {
+            args.prompt_file = self.prompt_file.clone();
+        }
+
+        if args.diff_command.is_none() {
+            args.diff_command = self.diff_command.clone();
+        }
+
+        if args.language.is_none() {
+            args.language = self.language.clone();
+        }
+
+        if args.case.is_none() {
+            args.case = self.case;
+        }
+
+        if let Some(action) = self.default_action
+            && !args.has_action()
+        {
+            match action {
+                DefaultAction::Commit => args.commit = true,
+                DefaultAction::Generate => args.generate = true,
+            }
+        }
+
+        if self.pipe_action == Some(PipeAction::Generate) && !args.has_action() && !std::io::stdout().is_terminal() {
+            args.generate = true;
+            args.quiet = true;
+        }
+
+        args.history |= self.history == Some(true);
+
+        if let Some(allowed) = &self.allowed_models
+            && !allowed.contains(&args.model)
+        {
+            return Err(GaiError::Config(format!(
+                "Model '{}' is not allowed by your configuration. Allowed models: {}",
+                args.model,
+                allowed.join(", ")
+            )).into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Formats a config file may use, in the order they are looked for. Only the
+/// first one found in each location is read.
+const CONFIG_EXTENSIONS: &[&str] = &["toml", "yaml", "yml", "json"];
+
+/// The first existing `<stem>.<ext>` in `dir`, or the TOML path if none exists.
+fn find_config(dir: &Path, stem: &str) -> PathBuf {
+    CONFIG_EXTENSIONS
+        .iter()
+        .map(|ext| dir.join(format!("{}.{}", stem, ext)))
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| dir.join(format!("{}.toml", stem)))
+}
+
+fn parse_layer(path: &Path, content: &str) -> Result<Value> {
+    let layer = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("yaml" | "yml") => serde_yaml::from_str(content)?,
+        Some("json") => serde_json::from_str(content)?,
+        _ => toml::from_str(content)?,
+    };
+
+    Ok(layer)
+}
+
+fn from_command_line(matches: &ArgMatches, id: &str) -> bool {
+    matches.value_source(id) == Some(ValueSource::CommandLine)
+}
+
+enum Layer {
+    File(PathBuf),
+    /// `gai.*` keys from git's own system, global and local config
+    GitConfig,
+}
+
+fn layers() -> Vec<Layer> {
+    let mut layers = vec![Layer::File(find_config(&system_config_dir(), "config"))];
+
+    if let Some(dir) = user_config_dir() {
+        layers.push(Layer::File(find_config(&dir, "config")));
+    }
+
+    layers.push(Layer::GitConfig);
+
+    if let Ok(root) = crate::repository_root() {
+        layers.push(Layer::File(find_config(&root, ".gai")));
+        // Machine-specific overrides, meant to be gitignored
+        layers.push(Layer::File(find_config(&root, ".gai.local")));
+    }
+
+    layers
+}
+
+/// Read `gai.provider`, `gai.model` and `gai.temperature` with `git config --get`.
+fn git_config_layer() -> Result<Value> {
+    let get = |key: &str| {
+        let output = Command::new("git").args(["config", "--get", key]).output().ok()?;
+        let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        (output.status.success() && !value.is_empty()).then_some(value)
+    };
+
+    let mut layer = serde_json::Map::new();
+
+    if let Some(provider) = get("gai.provider") {
+        layer.insert("provider".to_string(), Value::String(provider));
+    }
+
+    if let Some(model) = get("gai.model") {
+        layer.insert("model".to_string(), Value::String(model));
+    }
+
+    if let Some(temperature) = get("gai.temperature") {
+        let temperature = temperature
+            .parse::<f64>()
+            .with_context(|| GaiError::Config(format!("gai.temperature in git config is not a number: {}", temperature)))?;
+        layer.insert("temperature".to_string(), temperature.into());
+    }
+
+    Ok(Value::Object(layer))
+}
+
+/// Org-wide defaults, usually pushed by provisioning tooling.
+fn system_config_dir() -> PathBuf {
+    if cfg!(windows) {
+        let program_data = env::var("PROGRAMDATA").unwrap_or_else(|_| r"C:\ProgramData".to_string());
+        PathBuf::from(program_data).join("gai")
+    } else {
+        PathBuf::from("/etc/gai")
+    }
+}
+
+fn user_config_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var("APPDATA").ok().map(PathBuf::from)
+    } else {
+        env::var("XDG_CONFIG_HOME")
+            .ok()
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var("HOME").ok().map(|home| PathBuf::from(home).join(".config")))
+    };
+
+    base.map(|dir| dir.join("gai"))
+}
@@ -0,0 +1,85 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Output budget assumed for a single reply when estimating cost up front.
+pub const ESTIMATED_OUTPUT_TOKENS: usize = 256;
+
+/// Spend so far in this invocation, in millionths of a dollar.
+static SPENT_MICROS: AtomicU64 = AtomicU64::new(0);
+
+/// Approximate USD prices per million input and output tokens.
+pub const PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-4.1-nano", 0.10, 0.40),
+    ("gpt-4.1-mini", 0.40, 1.60),
+    ("gpt-4.1", 2.00, 8.00),
+    ("gpt-4o-mini", 0.15, 0.60),
+    ("gpt-4o", 2.50, 10.00),
+    ("o4-mini", 1.10, 4.40),
+    ("o3-mini", 1.10, 4.40),
+    ("o3", 2.00, 8.00),
+    ("gpt-3.5-turbo", 0.50, 1.50),
+    ("deepseek-chat", 0.27, 1.10),
+    ("deepseek-reasoner", 0.55, 2.19),
+    ("mistral-small-latest", 0.10, 0.30),
+    ("mistral-medium-latest", 0.40, 2.00),
+    ("mistral-large-latest", 2.00, 6.00),
+    ("codestral-latest", 0.30, 0.90),
+    ("gemini-2.5-pro", 1.25, 10.00),
+    ("gemini-2.5-flash", 0.30, 2.50),
+    ("gemini-2.5-flash-lite", 0.10, 0.40),
+    ("gemini-2.0-flash", 0.10, 0.40),
+];
+
+/// Approximate context windows in tokens, input and output together.
+pub const CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("gpt-4.1-nano", 1_047_576),
+    ("gpt-4.1-mini", 1_047_576),
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4o-mini", 128_000),
+    ("gpt-4o", 128_000),
+    ("o4-mini", 200_000),
+    ("o3-mini", 200_000),
+    ("o3", 200_000),
+    ("gpt-3.5-turbo", 16_385),
+    ("deepseek-chat", 64_000),
+    ("deepseek-reasoner", 64_000),
+    ("mistral-small-latest", 128_000),
+    ("mistral-medium-latest", 128_000),
+    ("mistral-large-latest", 128_000),
+    ("codestral-latest", 256_000),
+    ("gemini-2.5-pro", 1_048_576),
+    ("gemini-2.5-flash", 1_048_576),
+    ("gemini-2.5-flash-lite", 1_048_576),
+    ("gemini-2.0-flash", 1_048_576),
+];
+
+/// Characters per token assumed by `estimate_tokens`.
+pub const CHARS_PER_TOKEN: usize = 4;
+
+pub fn pricing(model: &str) -> Option<(f64, f64)> {
+    PRICES
+        .iter()
+        .find(|(name, _, _)| *name == model)
+        .map(|&(_, input, output)| (input, output))
+}
+
+/// Rough token count; about four characters per token for English and code.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+pub fn context_window(model: &str) -> Option<usize> {
+    CONTEXT_WINDOWS.iter().find(|(name, _)| *name == model).map(|&(_, tokens)| tokens)
+}
+
+pub fn cost(model: &str, input_tokens: usize, output_tokens: usize) -> Option<f64> {
+    let (input, output) = pricing(model)?;
+    Some((input_tokens as f64 * input + output_tokens as f64 * output) / 1_000_000.0)
+}
+
+pub fn spent() -> f64 {
+    SPENT_MICROS.load(Ordering::Relaxed) as f64 / 1_000_000.0
+}
+
+pub fn record(amount: f64) {
+    SPENT_MICROS.fetch_add((amount * 1_000_000.0).round() as u64, Ordering::Relaxed);
+}
@@ -0,0 +1,53 @@
+use std::fmt;
+
+/// Broad category of a failure, attached to errors where they originate so
+/// `main` can tell local problems apart from provider problems.
+#[derive(Debug)]
+pub enum GaiError {
+    Git(String),
+    Provider(String),
+    Config(String),
+    Io(String),
+}
+
+impl GaiError {
+    pub fn label(&self) -> &'static str {
+        match self {
+            GaiError::Git(_) => "Git error",
+            GaiError::Provider(_) => "Provider error",
+            GaiError::Config(_) => "Configuration error",
+            GaiError::Io(_) => "I/O error",
+        }
+    }
+
+    pub fn hint(&self) -> &'static str {
+        match self {
+            GaiError::Git(_) => "Check that you are inside a git repository and that git is installed and working.",
+            GaiError::Provider(_) => "The AI provider could not complete the request. Check your network connection, model name and account status.",
+            GaiError::Config(_) => "Check your config files, environment variables and command line flags.",
+            GaiError::Io(_) => "Check that the file exists and that you have permission to access it.",
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            GaiError::Config(_) => 2,
+            GaiError::Git(_) => 3,
+            GaiError::Provider(_) => 4,
+            GaiError::Io(_) => 5,
+        }
+    }
+}
+
+impl fmt::Display for GaiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GaiError::Git(message)
+            | GaiError::Provider(message)
+            | GaiError::Config(message)
+            | GaiError::Io(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for GaiError {}
@@ -0,0 +1,92 @@
+use anyhow::{Context, Result};
+use std::env;
+
+use crate::error::GaiError;
+
+const API_URL: &str = "https://api.github.com";
+
+/// Fetch the diff of the pull request at a `https://github.com/<owner>/<repo>/pull/<n>`
+/// URL, authenticating with `GITHUB_TOKEN` when it's set. `GITHUB_API_URL`
+/// points elsewhere for GitHub Enterprise.
+pub async fn fetch_pr_diff(url: &str) -> Result<String> {
+    let (owner, repo, number) = parse_pr_url(url)?;
+
+    let api_url = env::var("GITHUB_API_URL").unwrap_or_else(|_| API_URL.to_string());
+    let client = crate::http_client()?;
+    let mut request = client
+        .get(format!("{}/repos/{}/{}/pulls/{}", api_url.trim_end_matches('/'), owner, repo, number))
+        .header("User-Agent", "gitai")
+        .header("Accept", "application/vnd.github.diff");
+    if let Ok(token) = env::var("GITHUB_TOKEN") {
+        request = request.bearer_auth(token.trim());
+    }
+
+    let response = request.send().await.map_err(|e| crate::request_error(e, "GitHub API"))?;
+    let status = response.status();
+    if !status.is_success() {
+        let hint = match status.as_u16() {
+            401 | 403 | 404 if env::var("GITHUB_TOKEN").is_err() => "; set GITHUB_TOKEN for private repositories",
+            _ => "",
+        };
+        return Err(GaiError::Provider(format!("GitHub returned {} for {}/{}#{}{}", status, owner, repo, number, hint)).into());
+    }
+
+    let diff = response
+        .text()
+        .await
+        .context(GaiError::Provider("Failed to read the pull request diff".to_string()))?;
+    if diff.trim().is_empty() {
+        return Err(GaiError::Provider(format!("{}/{}#{} has no changes", owner, repo, number)).into());
+    }
+
+    Ok(diff)
+}
+
+/// The owner, repository and number in a pull request URL on github.com,
+/// or on the GitHub Enterprise host `GITHUB_API_URL` points at.
+fn parse_pr_url(url: &str) -> Result<(&str, &str, u64)> {
+    let path = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let host = path.split('/').next().unwrap_or_default();
+    if !is_github_host(host) {
+        return Err(GaiError::Config(format!("'{}' is not on github.com; --pr-url only reads GitHub pull requests", url)).into());
+    }
+
+    let pull_request = || {
+        let mut parts = path.split('/').skip(1);
+        let (owner, repo) = (parts.next()?, parts.next()?);
+        if parts.next()? != "pull" || owner.is_empty() || repo.is_empty() {
+            return None;
+        }
+
+        let number = parts.next()?.split(['#', '?']).next()?.parse().ok()?;
+        Some((owner, repo, number))
+    };
+    pull_request()
+        .ok_or_else(|| GaiError::Config(format!("'{}' is not a pull request URL like https://github.com/owner/repo/pull/42", url)).into())
+}
+
+fn is_github_host(host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    if host == "github.com" || host == "www.github.com" {
+        return true;
+    }
+
+    // GitHub Enterprise serves its API from https://<host>/api/v3
+    env::var("GITHUB_API_URL").is_ok_and(|api_url| {
+        let api_host = api_url.split_once("://").map_or(api_url.as_str(), |(_, rest)| rest).split('/').next().unwrap_or_default();
+        api_host.eq_ignore_ascii_case(&host)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_pr_url_accepts_tab_suffixes_and_rejects_other_pages() {
+        assert_eq!(parse_pr_url("https://github.com/tsoodo/gitai/pull/42/files").unwrap(), ("tsoodo", "gitai", 42));
+        assert_eq!(parse_pr_url("github.com/tsoodo/gitai/pull/7#discussion").unwrap(), ("tsoodo", "gitai", 7));
+        assert!(parse_pr_url("https://github.com/tsoodo/gitai/issues/42").is_err());
+        assert!(parse_pr_url("https://gitlab.com/tsoodo/gitai/pull/1").is_err());
+    }
+}
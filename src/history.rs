@@ -0,0 +1,175 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::GaiError;
+
+const HOOK_MARKER: &str = "# installed by gitai";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Entry {
+    pub timestamp: u64,
+    pub model: String,
+    pub message: String,
+    #[serde(default)]
+    pub sha: Option<String>,
+    /// Whether the commit message differs from the generated one
+    #[serde(default)]
+    pub edited: Option<bool>,
+}
+
+/// Append a generated message to the repository's history log.
+pub fn record(model: &str, message: &str) -> Result<()> {
+    let path = log_path()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context(GaiError::Io("Failed to create history directory".to_string()))?;
+    }
+
+    let entry = Entry {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        model: model.to_string(),
+        message: message.to_string(),
+        sha: None,
+        edited: None,
+    };
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| GaiError::Io(format!("Failed to open history log {}", path.display())))?;
+    writeln!(file, "{}", serde_json::to_string(&entry)?).context(GaiError::Io("Failed to write history log".to_string()))?;
+
+    Ok(())
+}
+
+/// Read every entry from the history log, oldest first.
+pub fn load() -> Result<Vec<Entry>> {
+    let path = log_path()?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = fs::read_to_string(&path)
+        .with_context(|| GaiError::Io(format!("Failed to read history log {}", path.display())))?;
+
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).context(GaiError::Io("Failed to parse history log entry".to_string())))
+        .collect()
+}
+
+/// Attach the SHA of HEAD to the latest uncommitted entry with HEAD's
+/// message, or failing that with its subject, in which case the message
+/// was edited. Called from the post-commit hook.
+pub fn record_commit() -> Result<()> {
+    let output = Command::new("git")
+        .args(["log", "-1", "--pretty=%H%n%B"])
+        .output()
+        .context(GaiError::Git("Failed to execute git log command".to_string()))?;
+
+    if !output.status.success() {
+        return Err(GaiError::Git("Failed to read HEAD commit".to_string()).into());
+    }
+
+    let log = String::from_utf8_lossy(&output.stdout);
+    let (sha, message) = log.split_once('\n').unwrap_or((log.as_ref(), ""));
+
+    let subject = |text: &str| text.trim().lines().next().unwrap_or_default().to_string();
+    let mut entries = load()?;
+    let uncommitted = || entries.iter().enumerate().rev().filter(|(_, e)| e.sha.is_none());
+    let found = uncommitted()
+        .find(|(_, e)| e.message.trim() == message.trim())
+        .or_else(|| uncommitted().find(|(_, e)| subject(&e.message) == subject(message)))
+        .map(|(i, _)| i);
+    let Some(index) = found else {
+        return Ok(());
+    };
+
+    let entry = &mut entries[index];
+    entry.sha = Some(sha.trim().to_string());
+    entry.edited = Some(entry.message.trim() != message.trim());
+
+    let mut content = String::new();
+    for entry in &entries {
+        content.push_str(&serde_json::to_string(entry)?);
+        content.push('\n');
+    }
+
+    let path = log_path()?;
+    fs::write(&path, content).with_context(|| GaiError::Io(format!("Failed to write history log {}", path.display())))?;
+
+    Ok(())
+}
+
+/// Install a post-commit hook that records commit SHAs in the history log.
+pub fn install_hook() -> Result<PathBuf> {
+    let path = git_path("hooks/post-commit")?;
+
+    if path.exists() {
+        let existing = fs::read_to_string(&path).unwrap_or_default();
+        if !existing.contains(HOOK_MARKER) {
+            return Err(GaiError::Io(format!(
+                "A post-commit hook already exists at {}. Add 'gitai --record-commit' to it manually.",
+                path.display()
+            )).into());
+        }
+    }
+
+    let exe = std::env::current_exe().context(GaiError::Io("Failed to locate the gitai executable".to_string()))?;
+    let script = format!("#!/bin/sh\n{}\n\"{}\" --record-commit || true\n", HOOK_MARKER, exe.display());
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context(GaiError::Io("Failed to create hooks directory".to_string()))?;
+    }
+    fs::write(&path, script).with_context(|| GaiError::Io(format!("Failed to write hook {}", path.display())))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).context(GaiError::Io("Failed to make hook executable".to_string()))?;
+    }
+
+    Ok(path)
+}
+
+/// Format a Unix timestamp as a UTC `YYYY-MM-DD` date.
+pub fn format_date(timestamp: u64) -> String {
+    // Days since 1970-01-01 to a civil date, per Howard Hinnant's algorithm
+    let days = (timestamp / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn log_path() -> Result<PathBuf> {
+    git_path("gai/history.jsonl")
+}
+
+fn git_path(path: &str) -> Result<PathBuf> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--git-path", path])
+        .output()
+        .context(GaiError::Git("Failed to execute git command. Is git installed?".to_string()))?;
+
+    if !output.status.success() {
+        return Err(GaiError::Git("Not inside a git repository".to_string()).into());
+    }
+
+    Ok(PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()))
+}
@@ -0,0 +1,276 @@
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// Config files commitlint looks for, in its own lookup order.
+const COMMITLINT_FILES: &[&str] = &[
+    ".commitlintrc",
+    ".commitlintrc.json",
+    ".commitlintrc.yaml",
+    ".commitlintrc.yml",
+    ".commitlintrc.js",
+    ".commitlintrc.cjs",
+    ".commitlintrc.mjs",
+    ".commitlintrc.ts",
+    "commitlint.config.js",
+    "commitlint.config.cjs",
+    "commitlint.config.mjs",
+    "commitlint.config.ts",
+];
+
+/// The parts of a `type(scope)!: description` subject line.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Subject<'a> {
+    pub kind: &'a str,
+    pub scope: Option<&'a str>,
+    pub breaking: bool,
+    pub description: &'a str,
+}
+
+pub fn parse_subject(subject: &str) -> Option<Subject<'_>> {
+    let (prefix, description) = subject.split_once(':')?;
+    let description = description.trim();
+
+    let (prefix, breaking) = match prefix.strip_suffix('!') {
+        Some(prefix) => (prefix, true),
+        None => (prefix, false),
+    };
+
+    let (kind, scope) = match prefix.split_once('(') {
+        Some((kind, scope)) => (kind, Some(scope.strip_suffix(')')?)),
+        None => (prefix, None),
+    };
+
+    let valid_kind = !kind.is_empty() && kind.chars().all(|c| c.is_ascii_alphanumeric());
+    if !valid_kind || description.is_empty() || scope == Some("") {
+        return None;
+    }
+
+    Some(Subject {
+        kind,
+        scope,
+        breaking,
+        description,
+    })
+}
+
+/// Split a message into everything before its footer block and the footers
+/// themselves (git trailers such as `Reviewed-by: Ann` or `Refs #42`), with
+/// any indented continuation lines folded into the footer they belong to.
+pub fn split_footers(message: &str) -> (&str, Vec<String>) {
+    let message = message.trim_end();
+    let Some(start) = message.rfind("\n\n").map(|i| i + 2) else {
+        return (message, Vec::new());
+    };
+
+    let mut footers: Vec<String> = Vec::new();
+    for line in message[start..].lines() {
+        if is_footer(line) {
+            footers.push(line.to_string());
+        } else if line.starts_with([' ', '\t'])
+            && let Some(last) = footers.last_mut()
+        {
+            last.push('\n');
+            last.push_str(line);
+        } else {
+            // A paragraph that isn't entirely footers is part of the body
+            return (message, Vec::new());
+        }
+    }
+
+    (message[..start].trim_end(), footers)
+}
+
+/// `Token: value` or `Token #value`, where the token is a single word or
+/// `BREAKING CHANGE`.
+fn is_footer(line: &str) -> bool {
+    let token_end = ["BREAKING CHANGE", "BREAKING-CHANGE"]
+        .iter()
+        .find(|token| line.starts_with(*token))
+        .map(|token| token.len())
+        .or_else(|| line.find(|c: char| !(c.is_alphanumeric() || c == '-')).filter(|&end| end > 0));
+
+    token_end.is_some_and(|end| line[end..].starts_with(": ") || line[end..].starts_with(" #"))
+}
+
+/// Rules that generated messages must satisfy.
+#[derive(Debug, Default, Clone)]
+pub struct Rules {
+    pub types: Option<Vec<String>>,
+    pub header_max_length: Option<usize>,
+}
+
+impl Rules {
+    /// Describe every way `message` breaks these rules.
+    pub fn check(&self, message: &str) -> Vec<String> {
+        let mut problems = Vec::new();
+        let header = message.lines().next().unwrap_or_default();
+
+        if let Some(max) = self.header_max_length
+            && header.chars().count() > max
+        {
+            problems.push(format!("the subject line must be at most {} characters", max));
+        }
+
+        if let Some(types) = &self.types {
+            match parse_subject(header) {
+                Some(subject) if !types.iter().any(|t| t == subject.kind) => {
+                    problems.push(format!("the type '{}' is not allowed; use one of: {}", subject.kind, types.join(", ")));
+                }
+                None => problems.push("the subject must follow the format <type>[optional scope]: <description>".to_string()),
+                _ => {}
+            }
+        }
+
+        problems
+    }
+}
+
+/// Best-effort read of `type-enum` and `header-max-length` from the first
+/// commitlint config found in `root`.
+pub fn load_commitlint_rules(root: &Path) -> Option<Rules> {
+    for name in COMMITLINT_FILES {
+        if let Ok(content) = fs::read_to_string(root.join(name)) {
+            return Some(parse_commitlint(&content));
+        }
+    }
+
+    // commitlint also reads a "commitlint" key from package.json
+    let package = fs::read_to_string(root.join("package.json")).ok()?;
+    let package = serde_json::from_str::<Value>(&package).ok()?;
+    package.get("commitlint").map(rules_from_json)
+}
+
+/// Read rules from the contents of a commitlint config, JSON or otherwise.
+fn parse_commitlint(content: &str) -> Rules {
+    match serde_json::from_str::<Value>(content) {
+        Ok(json) => rules_from_json(&json),
+        Err(_) => rules_from_text(content),
+    }
+}
+
+/// Read rules written as `[level, "always", value]` arrays.
+fn rules_from_json(config: &Value) -> Rules {
+    let rule = |name: &str| {
+        let rule = config.get("rules")?.get(name)?.as_array()?;
+        let enabled = rule.first()?.as_u64()? > 0 && rule.get(1)?.as_str()? == "always";
+        enabled.then(|| rule.get(2).cloned()).flatten()
+    };
+
+    Rules {
+        types: rule("type-enum").and_then(|types| {
+            types
+                .as_array()?
+                .iter()
+                .map(|t| t.as_str().map(str::to_string))
+                .collect()
+        }),
+        header_max_length: rule("header-max-length").and_then(|max| max.as_u64()).map(|max| max as usize),
+    }
+}
+
+/// Fallback for JavaScript and YAML configs: find the rule name and pull the
+/// quoted strings or the last number out of the bracketed value after it.
+fn rules_from_text(content: &str) -> Rules {
+    let rule = |name: &str| {
+        let rest = &content[content.find(name)? + name.len()..];
+        let start = rest.find('[')?;
+
+        let mut depth = 0;
+        for (i, c) in rest[start..].char_indices() {
+            match c {
+                '[' => depth += 1,
+                ']' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(&rest[start + 1..start + i]);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        None
+    };
+
+    let types = rule("type-enum").map(|value| {
+        let quoted = value.split(['\'', '"', '`']).skip(1).step_by(2).collect::<Vec<_>>();
+
+        // YAML flow lists usually leave the types unquoted
+        let tokens = if quoted.is_empty() {
+            value.split(',').map(|token| token.trim_matches(|c: char| c.is_whitespace() || c == '[' || c == ']')).collect()
+        } else {
+            quoted
+        };
+
+        tokens
+            .into_iter()
+            .filter(|token| !token.is_empty() && !matches!(*token, "always" | "never"))
+            .filter(|token| !token.chars().all(|c| c.is_ascii_digit()))
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    });
+
+    let header_max_length = rule("header-max-length").and_then(|value| {
+        value
+            .split(|c: char| !c.is_ascii_digit())
+            .rfind(|token| !token.is_empty())?
+            .parse()
+            .ok()
+    });
+
+    Rules {
+        types: types.filter(|types| !types.is_empty()),
+        header_max_length,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_footers_finds_the_trailer_block() {
+        let message = "feat: add login\n\nUses sessions.\n\nReviewed-by: Ann\nBREAKING CHANGE: drops\n  the old API\nRefs #42";
+
+        assert_eq!(
+            split_footers(message),
+            ("feat: add login\n\nUses sessions.", vec![
+                "Reviewed-by: Ann".to_string(),
+                "BREAKING CHANGE: drops\n  the old API".to_string(),
+                "Refs #42".to_string(),
+            ])
+        );
+        assert_eq!(split_footers("feat: add login\n\nThis is prose: really"), ("feat: add login\n\nThis is prose: really", vec![]));
+    }
+
+    #[test]
+    fn parse_commitlint_reads_json_and_yaml_rules() {
+        let json = r#"{"rules": {"type-enum": [2, "always", ["feat", "fix"]], "header-max-length": [2, "always", 72]}}"#;
+        let rules = parse_commitlint(json);
+        assert_eq!(rules.types, Some(vec!["feat".to_string(), "fix".to_string()]));
+        assert_eq!(rules.header_max_length, Some(72));
+
+        let disabled = parse_commitlint(r#"{"rules": {"type-enum": [0, "always", ["feat"]], "header-max-length": [2, "never", 50]}}"#);
+        assert_eq!((disabled.types, disabled.header_max_length), (None, None));
+
+        let yaml = "rules:\n  type-enum: [2, always, [feat, fix, docs]]\n  header-max-length: [2, always, 100]\n";
+        let rules = parse_commitlint(yaml);
+        assert_eq!(rules.types, Some(vec!["feat".to_string(), "fix".to_string(), "docs".to_string()]));
+        assert_eq!(rules.header_max_length, Some(100));
+    }
+
+    #[test]
+    fn rules_check_reports_disallowed_types_and_long_headers() {
+        let rules = Rules {
+            types: Some(vec!["feat".to_string(), "fix".to_string()]),
+            header_max_length: Some(20),
+        };
+
+        assert!(rules.check("feat: add login\n\nA body line that is much longer than twenty").is_empty());
+        assert_eq!(rules.check("chore: bump"), vec!["the type 'chore' is not allowed; use one of: feat, fix".to_string()]);
+        assert_eq!(rules.check("fix: a subject well over twenty").len(), 1);
+        assert_eq!(rules.check("Add a longer login form").len(), 2);
+        assert!(Rules::default().check("anything at all goes here").is_empty());
+    }
+}
@@ -1,12 +1,115 @@
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use dialoguer::{Confirm, Input, MultiSelect, Select};
 use dotenv::dotenv;
 use reqwest::Client;
+use reqwest::header::{HeaderName, HeaderValue};
 use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::HashSet;
 use std::env;
-use std::process::Command;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::io::{IsTerminal, Write};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::Semaphore;
 
-#[derive(Parser, Debug)]
+mod cache;
+mod config;
+mod cost;
+mod error;
+mod github;
+mod history;
+mod lint;
+mod providers;
+mod spelling;
+mod update;
+
+use config::{Config, DoNotSendMode};
+use error::GaiError;
+use lint::Rules;
+use providers::{DEEPSEEK, HuggingFace, MISTRAL, OPENAI, Ollama, OpenAICompatible, Params, Vertex};
+use providers::Provider as _;
+
+/// Defaults shared by the command line help, config layering and generation,
+/// so they can't drift apart.
+const DEFAULT_PROVIDER: Provider = Provider::OpenAI;
+const DEFAULT_MODEL: &str = "gpt-4.1-nano";
+const DEFAULT_TEMPERATURE: f32 = 1.0;
+const DEFAULT_PR_BASE: &str = "origin/main";
+
+/// Wraps the diff in the user message; `{diff}` is replaced with the diff.
+const DEFAULT_USER_PROMPT: &str = "Analyze this diff and generate a conventional commit message. Pay attention to what the code actually does, not just file names:\n\n{diff}";
+
+/// How many recent commits to compare against when looking for a revert.
+const REVERT_SEARCH_DEPTH: usize = 20;
+
+/// How long to wait for a TCP connection to the provider.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Retries after a rate limit or server error.
+const DEFAULT_RETRIES: u32 = 2;
+
+/// How many times the model may retry a message that breaks the commit rules.
+const MAX_FIX_ATTEMPTS: usize = 2;
+/// System prompt a repository can commit for everyone, relative to its root.
+const REPO_PROMPT_FILE: &str = ".gai/prompt.md";
+
+/// Size in KiB above which a newly added file needs --force to be committed.
+const DEFAULT_LARGE_FILE_KB: u64 = 1024;
+
+/// Used for --allow-empty commits when no message is given.
+const EMPTY_COMMIT_MESSAGE: &str = "chore: empty commit";
+/// How many requests may be in flight at once unless --concurrency says otherwise.
+const DEFAULT_CONCURRENCY: usize = 3;
+
+/// Caps simultaneous provider requests across every multi-call mode.
+static REQUEST_SLOTS: OnceLock<Semaphore> = OnceLock::new();
+/// --format, for `report_error`, which runs after `args` is gone.
+static ERROR_FORMAT: OnceLock<ErrorFormat> = OnceLock::new();
+/// How often --watch checks the index, and how long it must stay unchanged
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Returned in place of a reply once --show-prompt has printed the prompt, so
+/// everything on the way back up to `main` can clean up before it exits.
+#[derive(Debug)]
+struct PromptShown;
+
+impl std::fmt::Display for PromptShown {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the prompt was shown and not sent")
+    }
+}
+
+impl std::error::Error for PromptShown {}
+
+/// The whole index, parked as a tree while some of it is unstaged to commit
+/// the rest. Dropping it puts the index back, so an error, --show-prompt or
+/// Ctrl-C can't leave files unstaged.
+struct ParkedIndex {
+    tree: String,
+}
+
+impl ParkedIndex {
+    fn park() -> Result<Self> {
+        Ok(ParkedIndex { tree: git(&["write-tree"])?.trim().to_string() })
+    }
+
+    /// Restage everything parked that isn't committed by now.
+    fn restore(&self) -> Result<()> {
+        git(&["read-tree", &self.tree]).map(|_| ())
+    }
+}
+
+impl Drop for ParkedIndex {
+    fn drop(&mut self) {
+        let _ = self.restore();
+    }
+}
+
+#[derive(Parser, Debug, Clone)]
 #[command(
     name = "gitai",
     author = "Ian Irizarry",
@@ -15,6 +118,13 @@ use std::process::Command;
     long_about = None
 )]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    /// Run as if gitai was started in DIR, like `git -C`, e.g. in another worktree
+    #[arg(short = 'C', value_name = "DIR")]
+    directory: Option<PathBuf>,
+
     /// Generate a commit message from staged changes
     #[arg(short, long)]
     generate: bool,
@@ -23,191 +133,5630 @@ struct Args {
     #[arg(short, long)]
     commit: bool,
 
-    /// Model to use (default: o4-mini)
-    #[arg(short, long, default_value = "gpt-4.1-nano")]
+    /// Allow committing with nothing staged, using --message or a prompted message
+    #[arg(long)]
+    allow_empty: bool,
+
+    /// Push to the current branch's upstream after committing
+    #[arg(long, requires = "commit", conflicts_with = "print_command")]
+    push: bool,
+
+    /// Print the message through a template instead, e.g. "{type}: {description}".
+    /// Fields: {type} {scope} {breaking} {subject} {description} {body} {footers} {message}
+    #[arg(long, value_name = "TEMPLATE", conflicts_with = "print_command")]
+    output_template: Option<String>,
+
+    /// Print the exact git commit command --commit would run, without running it
+    #[arg(long, requires = "commit")]
+    print_command: bool,
+
+    /// Skip the pre-commit and commit-msg hooks (git commit --no-verify)
+    #[arg(long)]
+    no_verify: bool,
+
+    /// Stage every change and untracked file (`git add -A`), generate a message,
+    /// and commit it once confirmed
+    #[arg(short = 'A', long, conflicts_with_all = [
+        "interactive", "auto_split", "batch", "pr", "pr_url", "patch", "stash", "watch", "explain", "print_command",
+    ])]
+    commit_all: bool,
+
+    /// Commit without asking for confirmation with --commit-all
+    #[arg(short, long, requires = "commit_all")]
+    yes: bool,
+
+    /// After committing, record the provider, model, temperature and patch ID
+    /// in a git note on the commit, leaving the message itself alone
+    #[arg(long)]
+    note: bool,
+
+    /// Refuse to commit while a staged file also has unstaged changes, since
+    /// the commit wouldn't match what's in the working tree
+    #[arg(long)]
+    require_clean_worktree: bool,
+
+    /// Commit even in detached HEAD state without asking, and allow staged
+    /// changes that contain merge conflict markers or large new files
+    #[arg(long)]
+    force: bool,
+
+    /// Date for the commit, passed to git commit --date
+    #[arg(long)]
+    date: Option<String>,
+
+    /// Also copy the generated message to the system clipboard
+    #[arg(long)]
+    copy: bool,
+
+    /// Print only the generated message, without decoration or hints
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Show intermediate steps, such as the --think summary or --spellcheck
+    /// corrections, on stderr
+    #[arg(short, long, conflicts_with = "quiet")]
+    verbose: bool,
+
+    /// Correct common misspellings in the generated message, leaving code
+    /// and words that appear in the diff alone
+    #[arg(long)]
+    spellcheck: bool,
+
+    /// Have the model summarize the diff first, then write the message from
+    /// that summary; better subjects for complex diffs at twice the cost
+    #[arg(long, conflicts_with = "local")]
+    think: bool,
+
+    /// Pick staged files for each commit in turn, generating a message for each
+    #[arg(short, long, conflicts_with_all = ["since", "batch"])]
+    interactive: bool,
+
+    /// Pick hunks to stage with `git add --patch` first, then generate a message for them
+    #[arg(short = 'p', long, conflicts_with_all = ["since", "stash", "batch", "watch", "pr"])]
+    patch: bool,
+
+    /// Experimental: classify each staged hunk by commit type and make one commit per type
+    #[arg(long, conflicts_with_all = ["since", "stash", "interactive", "batch", "commit", "explain", "pr", "watch"])]
+    auto_split: bool,
+
+    /// Make one commit per monorepo package the staged changes touch, writing
+    /// all of their messages (up to --concurrency at a time) before committing any
+    #[arg(long, conflicts_with_all = [
+        "since", "stash", "interactive", "auto_split", "batch", "commit", "commit_all", "explain", "pr", "pr_url", "watch", "patch",
+        "diff_command",
+    ])]
+    split_by_package: bool,
+
+    /// Only these staged files, set for each package by --split-by-package
+    #[arg(skip)]
+    only_paths: Vec<String>,
+
+    /// Build the message from the changed files with simple rules instead of
+    /// asking a provider: no network, no cost, same message every time
+    #[arg(long, conflicts_with_all = ["explain", "pr", "batch", "auto_split", "score", "min_score"])]
+    local: bool,
+
+    /// Explain what the staged diff does instead of writing a commit message
+    #[arg(short, long, conflicts_with = "commit")]
+    explain: bool,
+
+    /// Regenerate the suggested message whenever the index changes, until Ctrl-C
+    #[arg(long, conflicts_with_all = ["commit", "interactive", "explain", "batch"])]
+    watch: bool,
+
+    /// Write a squash commit message for a GitHub pull request, fetched with
+    /// GITHUB_TOKEN from its URL; no checkout needed. With --explain, explain it instead
+    #[arg(long, value_name = "URL", conflicts_with_all = [
+        "commit", "pr", "interactive", "auto_split", "batch", "watch", "patch", "stash", "since", "changed_since_pull",
+        "local", "redact_paths",
+    ])]
+    pr_url: Option<String>,
+
+    /// Write a pull request title and description for the current branch
+    #[arg(long, conflicts_with_all = ["commit", "explain"])]
+    pr: bool,
+
+    /// Re-generate only the scope of an existing message (see --message)
+    #[arg(long, conflicts_with_all = ["commit", "explain", "pr", "fix_type"])]
+    fix_scope: bool,
+
+    /// Re-generate only the type of an existing message (see --message)
+    #[arg(long, conflicts_with_all = ["commit", "explain", "pr"])]
+    fix_type: bool,
+
+    /// Use this message instead of generating one, still applying --case, footers and the commit rules.
+    /// Also the input for --fix-scope and --fix-type (read from stdin if omitted) and --regenerate
+    #[arg(long, conflicts_with_all = ["interactive", "auto_split", "batch"])]
+    message: Option<String>,
+
+    /// Generate a new message to replace --message, keeping its trailers (e.g. Reviewed-by:)
+    #[arg(long, requires = "message")]
+    regenerate: bool,
+
+    /// Base branch the pull request is compared against
+    #[arg(long, value_name = "REF", default_value = DEFAULT_PR_BASE)]
+    base: String,
+
+    /// AI provider to send the diff to
+    #[arg(long, value_enum, default_value_t = DEFAULT_PROVIDER)]
+    provider: Provider,
+
+    /// Model to use (other providers default to their own model)
+    #[arg(short, long, default_value = DEFAULT_MODEL)]
     model: String,
 
-    /// Temperature for generation (0.0-2.0, default: 1)
-    #[arg(short, long, default_value = "1")]
-    temperature: f32,
+    #[arg(short, long, help = format!("Temperature for generation (0.0-2.0) [default: {}]", DEFAULT_TEMPERATURE))]
+    temperature: Option<f32>,
+
+    /// Pick the temperature from the diff size (ignored if --temperature is set)
+    #[arg(long)]
+    temperature_auto: bool,
+
+    /// Generate N messages and choose one of them
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=10))]
+    candidates: usize,
+
+    /// Pick the best of the --candidates without asking: one that passes the commit rules,
+    /// is conventional, has no emoji and is closest to the target subject length
+    #[arg(long)]
+    auto_pick: bool,
+
+    /// Generate one message per temperature (e.g. 0.2,0.7,1.2) and print them side by side
+    #[arg(long, value_name = "TEMPERATURES", value_delimiter = ',', conflicts_with_all = ["commit", "interactive", "batch", "watch"])]
+    temperature_sweep: Vec<f32>,
+
+    /// Describe everything changed since REF, including unstaged edits.
+    /// With --commit, only the staged changes are committed.
+    #[arg(long, value_name = "REF")]
+    since: Option<String>,
+
+    /// Describe your local changes, committed or not, since the branch's
+    /// upstream tracking branch, e.g. to sum them up before pushing
+    #[arg(long, conflicts_with_all = ["since", "stash", "interactive", "batch", "patch", "auto_split"])]
+    changed_since_pull: bool,
+
+    /// Describe the changes in a stash instead of the staged ones (default stash@{0})
+    #[arg(long, value_name = "REF", num_args = 0..=1, default_missing_value = "stash@{0}", conflicts_with_all = ["since", "interactive", "commit"])]
+    stash: Option<String>,
+
+    /// Number of context lines around each change in the diff (git's -U)
+    #[arg(long, value_name = "N")]
+    context: Option<u32>,
+
+    /// Show whole changed functions in the diff (git's -W); uses more tokens
+    #[arg(long)]
+    function_context: bool,
+
+    /// Diff algorithm passed to git diff
+    #[arg(long, value_enum, value_name = "ALGORITHM")]
+    diff_algorithm: Option<DiffAlgorithm>,
+
+    /// Only include files with these extensions in the diff (e.g. rs,toml)
+    #[arg(long, value_name = "EXT", value_delimiter = ',')]
+    include_ext: Vec<String>,
+
+    /// Append `git diff --summary` (renames, mode changes, created and deleted files) to the diff
+    #[arg(long, conflicts_with = "redact_paths")]
+    include_summary: bool,
+
+    /// Collapse unchanged context lines in the diff into short markers to save tokens
+    #[arg(long, conflicts_with = "function_context")]
+    compact_diff: bool,
+
+    /// Send at most this many changed lines of each file's diff, so one huge
+    /// file doesn't crowd out the others
+    #[arg(long, value_name = "LINES")]
+    diff_file_limit: Option<usize>,
+
+    /// Send at most this many bytes of diff, condensing a bigger one; defaults
+    /// to what fits the model's context window, or 100000 bytes when gitai
+    /// doesn't know it. 0 sends the whole diff, however big
+    #[arg(long, value_name = "BYTES")]
+    max_diff_bytes: Option<usize>,
+
+    /// Replace file paths in the diff with tokens like file1.rs before sending it,
+    /// and put the real paths back into the reply
+    #[arg(long, conflicts_with_all = ["pr", "batch", "auto_split", "fix_scope", "fix_type"])]
+    redact_paths: bool,
+
+    /// Shell command that prints the diff to describe, instead of the staged
+    /// changes, e.g. "jj diff --git" for Jujutsu
+    #[arg(long, value_name = "COMMAND", conflicts_with_all = ["since", "stash", "changed_since_pull", "interactive", "auto_split"])]
+    diff_command: Option<String>,
+
+    /// Let the model describe changes that only bump dependencies, instead of
+    /// listing the bumped packages in a chore(deps) message
+    #[arg(long)]
+    no_deps_message: bool,
+
+    /// Don't suggest a scope from the monorepo package containing the changes
+    #[arg(long)]
+    no_auto_scope: bool,
+
+    /// Halve the temperature each time a message is sent back for breaking the commit rules
+    #[arg(long)]
+    retry_different_temperature: bool,
+
+    /// Normalize the first letter of the subject's description
+    #[arg(long, value_enum, value_name = "CASE")]
+    case: Option<SubjectCase>,
+
+    /// Keep emoji the model puts in the message; they are stripped by default
+    /// (except with the gitmoji preset)
+    #[arg(long)]
+    allow_emoji: bool,
+
+    /// Turn smart quotes, dashes and ellipses in the final message into ASCII, keeping other characters
+    #[arg(long)]
+    normalize_punctuation: bool,
+
+    /// Replace or drop non-ASCII characters such as emoji in the final message
+    #[arg(long)]
+    ascii_only: bool,
+
+    /// Append a "Changes: N files, +A/-D" footer computed from the diff
+    #[arg(long)]
+    stat_footer: bool,
+
+    /// Built-in style of commit message to ask for
+    #[arg(long, value_enum)]
+    preset: Option<Preset>,
+
+    /// Read the system prompt from FILE instead of using a preset
+    #[arg(long, value_name = "FILE")]
+    prompt_file: Option<PathBuf>,
+
+    /// Use the clipboard contents as the system prompt, for quick experiments
+    #[arg(long, conflicts_with = "prompt_file")]
+    prompt_from_clipboard: bool,
+
+    /// Add a body of bullet points below the subject line
+    #[arg(long)]
+    body: bool,
+
+    /// Leave out the body anyway when the diff changes at most N lines (default 2)
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "2")]
+    no_body_if_small: Option<usize>,
+
+    /// Keep at most N bullet points in the body
+    #[arg(long, value_name = "N", requires = "body")]
+    max_bullets: Option<usize>,
+
+    /// How much explanation the message should contain
+    #[arg(long, value_enum)]
+    detail: Option<Detail>,
+
+    /// Don't tell the model the current branch name
+    #[arg(long)]
+    no_branch_hint: bool,
+
+    /// Language-specific prompt hint to use instead of detecting one ("none" to disable)
+    #[arg(long, value_name = "LANGUAGE")]
+    language: Option<String>,
+
+    /// Add a "Closes #ISSUE" footer (repeatable); "auto" takes the number from the branch name
+    #[arg(long, value_name = "ISSUE")]
+    closes: Vec<String>,
+
+    /// Template for the user message; {diff} is replaced with the diff
+    #[arg(long, value_name = "TEMPLATE")]
+    user_prompt: Option<String>,
+
+    /// Ask the model to rate its message from 0 to 10 (one extra API call)
+    #[arg(long)]
+    score: bool,
+
+    /// With --commit, only commit without asking if the model rates its message at least this high (0-10)
+    #[arg(long, value_name = "N", requires = "commit", value_parser = clap::value_parser!(u8).range(0..=10))]
+    min_score: Option<u8>,
+
+    /// Print the exact prompt that would be sent and exit without calling the API
+    #[arg(long)]
+    show_prompt: bool,
+
+    /// Have the model fill a JSON schema of type, scope, description and body (OpenAI only)
+    #[arg(long)]
+    json_schema: bool,
+
+    /// Refuse to send requests whose estimated cost would exceed this many USD
+    #[arg(long, value_name = "USD")]
+    max_cost: Option<f64>,
+
+    /// Ask before sending requests estimated to cost more than this many USD
+    /// in all, e.g. every --candidates; without a terminal to ask on, only
+    /// --max-cost applies
+    #[arg(long, value_name = "USD")]
+    confirm_on_large_cost: Option<f64>,
+
+    /// Most API calls this invocation may make, counting every retry and regeneration
+    #[arg(long, value_name = "CALLS")]
+    retry_budget: Option<usize>,
+
+    /// Extra HTTP header to send with the API request ("Key: Value", repeatable)
+    #[arg(long = "header", value_name = "HEADER", value_parser = parse_header)]
+    headers: Vec<(String, String)>,
+
+    /// Start descriptions of TYPE commits with VERB, e.g. fix=resolve (repeatable).
+    /// Overrides the type_verbs config; an empty VERB turns it off for that type.
+    #[arg(long = "type-verb", value_name = "TYPE=VERB", value_parser = parse_type_verb)]
+    type_verbs: Vec<(String, String)>,
+
+    /// Extra parameter to merge into the request body ("key=value", repeatable), e.g. top_p=0.9.
+    /// Numbers, true, false and null are sent as JSON; anything else as a string.
+    /// Providers may reject parameters they don't know.
+    #[arg(long = "extra-param", value_name = "KEY=VALUE", value_parser = parse_extra_param)]
+    extra_params: Vec<(String, Value)>,
+
+    /// How to report failures: as text, or as GitHub Actions `::error` annotations for CI logs
+    #[arg(long, value_enum, default_value_t = ErrorFormat::Human)]
+    format: ErrorFormat,
+
+    /// Follow provider errors with an explanation and suggested fix when the cause is a familiar one
+    #[arg(long)]
+    explain_failure: bool,
+
+    /// Write every raw provider response of this run to this file, for debugging and bug reports
+    #[arg(long, value_name = "PATH")]
+    dump_response: Option<PathBuf>,
+
+    /// Fold the system prompt into the first user message, for endpoints that reject the system role
+    #[arg(long)]
+    no_system_role: bool,
+
+    /// Send requests to this API base URL instead of the provider's, e.g. a proxy or mock server
+    #[arg(long, value_name = "URL")]
+    base_url: Option<String>,
+
+    /// Check that the model exists for the provider before sending the diff
+    #[arg(long)]
+    verify_model: bool,
+
+    /// Most provider requests to have in flight at once, across all modes
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_CONCURRENCY, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    concurrency: usize,
+
+    /// How many times to retry a request after a rate limit, server error or --attempt-timeout
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_RETRIES)]
+    retries: u32,
+
+    /// Give up on a single request attempt, and retry it, after this many seconds
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    attempt_timeout: Option<u64>,
+
+    /// Give up on a request after this many seconds, however many retries are left
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    total_timeout: Option<u64>,
+
+    /// OpenAI endpoint to call: chat completions, or the newer Responses API
+    #[arg(long, value_enum, value_name = "API", default_value = "chat")]
+    api: Api,
+
+    /// OpenAI organization to bill, sent as the OpenAI-Organization header
+    #[arg(long, value_name = "ID", env = "OPENAI_ORG_ID")]
+    openai_org: Option<String>,
+
+    /// OpenAI project for project-scoped keys, sent as the OpenAI-Project header
+    #[arg(long, value_name = "ID", env = "OPENAI_PROJECT_ID")]
+    openai_project: Option<String>,
+
+    /// Allow --header to replace the Authorization header
+    #[arg(long)]
+    allow_auth_override: bool,
+
+    /// Print supported providers, known models and features as JSON
+    #[arg(long)]
+    capabilities: bool,
+
+    /// Generate and cache a message for the staged changes so the next run is instant
+    #[arg(long, conflicts_with_all = ["commit", "interactive", "batch", "watch"])]
+    prewarm: bool,
+
+    /// Check an existing message against the commit rules, without an API
+    /// call, and exit non-zero with what's wrong. Reads FILE, or stdin when
+    /// none is given; as a commit-msg hook: gitai --validate-only "$1"
+    #[arg(long, value_name = "FILE", num_args = 0..=1, default_missing_value = "-")]
+    validate_only: Option<PathBuf>,
+
+    /// Install a post-commit hook that records commit SHAs in the history log
+    #[arg(long)]
+    install_hook: bool,
+
+    /// Log the generated message to the history log, as `history = true` does
+    #[arg(long)]
+    history: bool,
+
+    /// Act as git's editor during a rebase: write a fresh message for each
+    /// reworded or edited commit into FILE, and open $EDITOR for the todo list.
+    /// Use as GIT_EDITOR="gitai --rebase-editor" git rebase -i
+    #[arg(long, value_name = "FILE")]
+    rebase_editor: Option<PathBuf>,
+
+    /// Compare the index with this commit or tree instead of HEAD, set when
+    /// the message being edited is HEAD's own, as with --amend, and for each
+    /// group of --auto-split
+    #[arg(skip)]
+    staged_base: Option<String>,
+
+    /// Record HEAD in the history log (used by the post-commit hook)
+    #[arg(long, hide = true)]
+    record_commit: bool,
+
+    /// Generate one message per diff file in a single API call
+    #[arg(long, value_name = "FILE", num_args = 1.., conflicts_with = "commit")]
+    batch: Vec<PathBuf>,
+
+    /// Settings loaded from config files
+    #[arg(skip)]
+    config: Config,
 }
 
-#[derive(Serialize, Debug)]
-struct OpenAIRequest {
-    model: String,
-    messages: Vec<Message>,
-    temperature: f32,
+/// Built-in system prompts for common commit message styles.
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum Preset {
+    Conventional,
+    ConventionalWithBody,
+    Gitmoji,
+    Plain,
+    Jira,
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Provider {
+    #[value(name = "openai")]
+    OpenAI,
+    #[value(name = "huggingface")]
+    HuggingFace,
+    #[value(name = "deepseek")]
+    DeepSeek,
+    #[value(name = "mistral")]
+    Mistral,
+    #[value(name = "vertex")]
+    Vertex,
+    #[value(name = "ollama")]
+    Ollama,
+}
+
+impl Provider {
+    /// Model used when neither the command line nor a config file picks one.
+    fn default_model(self) -> &'static str {
+        match self {
+            Provider::OpenAI => DEFAULT_MODEL,
+            Provider::HuggingFace => "mistralai/Mistral-7B-Instruct-v0.3",
+            Provider::DeepSeek => "deepseek-chat",
+            Provider::Mistral => "mistral-small-latest",
+            Provider::Vertex => "gemini-2.5-flash",
+            Provider::Ollama => "llama3.2",
+        }
+    }
+
+    /// Whether a model name from the price list belongs to this provider.
+    fn offers_model(self, model: &str) -> bool {
+        let deepseek = model.starts_with("deepseek");
+        let mistral = model.starts_with("mistral") || model.starts_with("codestral");
+        let gemini = model.starts_with("gemini");
+        match self {
+            Provider::OpenAI => !deepseek && !mistral && !gemini,
+            Provider::DeepSeek => deepseek,
+            Provider::Mistral => mistral,
+            Provider::Vertex => gemini,
+            Provider::HuggingFace | Provider::Ollama => false,
+        }
+    }
+
+    /// Connection details for providers with an OpenAI-compatible API.
+    fn openai_compatible(self) -> Option<&'static OpenAICompatible> {
+        match self {
+            Provider::OpenAI => Some(&OPENAI),
+            Provider::DeepSeek => Some(&DEEPSEEK),
+            Provider::Mistral => Some(&MISTRAL),
+            Provider::HuggingFace | Provider::Vertex | Provider::Ollama => None,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Commands {
+    /// Update gitai to the latest GitHub release, checking the binary against
+    /// the SHA-256 the release publishes
+    SelfUpdate {
+        /// Only report whether a newer release exists
+        #[arg(long)]
+        check_only: bool,
+    },
+    /// Show previously generated messages from the history log
+    History {
+        /// Only show messages containing this text (case-insensitive)
+        #[arg(long, value_name = "TERM")]
+        grep: Option<String>,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Api {
+    /// /chat/completions
+    Chat,
+    /// /responses
+    Responses,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum DiffAlgorithm {
+    Myers,
+    Patience,
+    Histogram,
+    Minimal,
+}
+
+impl Args {
+    /// Whether a subcommand or any flag choosing what to do was given, as
+    /// opposed to flags that only tune how it's done.
+    fn has_action(&self) -> bool {
+        self.command.is_some()
+            || self.generate
+            || self.commit
+            || self.commit_all
+            || self.explain
+            || self.pr
+            || self.pr_url.is_some()
+            || self.interactive
+            || self.auto_split
+            || self.split_by_package
+            || self.watch
+            || self.prewarm
+            || self.fix_scope
+            || self.fix_type
+            || self.capabilities
+            || self.record_commit
+            || self.install_hook
+            || self.validate_only.is_some()
+            || self.verify_model
+            || !self.batch.is_empty()
+            || !self.temperature_sweep.is_empty()
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ErrorFormat {
+    Human,
+    /// `::error title=...::message`, which GitHub Actions turns into an annotation
+    Ci,
+}
+
+/// How much explanation the generated message should carry.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Detail {
+    Low,
+    Medium,
+    High,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Detail {
+    fn instruction(self) -> &'static str {
+        match self {
+            Detail::Low => "Keep the description as short as possible and don't explain why the change was made.",
+            Detail::Medium => "Say what changed and, where it isn't obvious, briefly why.",
+            Detail::High => "Describe what changed and explain the rationale behind it, including any notable trade-offs.",
+        }
+    }
+}
+
+/// How to capitalize the description in the subject line.
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum SubjectCase {
+    /// "feat: add login"
+    Lower,
+    /// "feat: Add login"
+    Sentence,
+    /// Leave it as the model wrote it
+    Preserve,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 struct Message {
     role: String,
     content: String,
 }
 
 #[derive(Deserialize, Debug)]
-struct OpenAIResponse {
-    choices: Vec<Choice>,
-    #[serde(default)]
-    error: Option<OpenAIError>,
+struct BatchResponse {
+    messages: Vec<String>,
 }
 
 #[derive(Deserialize, Debug)]
-struct Choice {
-    message: Message,
+struct HunkTypes {
+    types: Vec<String>,
 }
 
+/// A commit message split into the fields of `commit_message_schema`.
 #[derive(Deserialize, Debug)]
-struct OpenAIError {
-    message: String,
+struct StructuredMessage {
+    #[serde(rename = "type")]
+    kind: String,
+    scope: Option<String>,
+    description: String,
+    body: Option<String>,
+}
+
+impl StructuredMessage {
+    fn assemble(&self) -> String {
+        let mut message = self.kind.trim().to_string();
+        if let Some(scope) = self.scope.as_deref().map(str::trim).filter(|scope| !scope.is_empty()) {
+            message.push_str(&format!("({})", scope));
+        }
+        message.push_str(&format!(": {}", self.description.trim()));
+        if let Some(body) = self.body.as_deref().map(str::trim).filter(|body| !body.is_empty()) {
+            message.push_str(&format!("\n\n{}", body));
+        }
+        message
+    }
 }
 
 fn load_system_prompt() -> &'static str {
     include_str!("commit_prompt.txt")
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Load environment variables from .env file
-    dotenv().ok();
+fn load_gitmoji_prompt() -> &'static str {
+    include_str!("gitmoji_prompt.txt")
+}
 
-    // Parse command line arguments
-    let args = Args::parse();
+fn load_plain_prompt() -> &'static str {
+    include_str!("plain_prompt.txt")
+}
 
-    if args.generate || args.commit {
-        // Generate commit message
-        let commit_message = generate_commit_message(&args.model, args.temperature).await?;
+fn load_jira_prompt() -> &'static str {
+    include_str!("jira_prompt.txt")
+}
 
-        if args.commit {
-            // Use the generated message to create a commit
-            create_commit(&commit_message)?;
-            println!("✅ Committed with message: \"{}\"", commit_message);
-        } else {
-            // Just print the message
-            println!("📝 Generated commit message:");
-            println!("{}", commit_message);
-            println!("\nTo use this message:");
-            println!("git commit -m \"{}\"", commit_message);
+/// The system prompt from --prompt-file or the clipboard, or else the one
+/// for the preset.
+fn preset_system_prompt(args: &Args) -> Result<String> {
+    if args.prompt_from_clipboard {
+        match paste_from_clipboard() {
+            Ok(prompt) if !prompt.trim().is_empty() => return Ok(prompt),
+            Ok(_) => eprintln!("⚠️ The clipboard is empty; using the built-in prompt"),
+            Err(e) => eprintln!("⚠️ {}; using the built-in prompt", e),
         }
-    } else {
-        println!("🤖 gitai - AI Powered Git Commit Messages");
-        println!("Use --generate (-g) to create a commit message");
-        println!("Use --commit (-c) to commit with the generated message");
-        println!("\nRun 'gitai --help' for more options");
     }
 
-    Ok(())
+    if let Some(path) = &args.prompt_file {
+        return fs::read_to_string(path).with_context(|| GaiError::Io(format!("Failed to read prompt file {}", path.display())));
+    }
+
+    // A prompt versioned with the code, unless a style was picked explicitly
+    if args.preset.is_none()
+        && let Ok(root) = repository_root()
+    {
+        let path = root.join(REPO_PROMPT_FILE);
+        if path.is_file() {
+            return fs::read_to_string(&path).with_context(|| GaiError::Io(format!("Failed to read prompt file {}", path.display())));
+        }
+    }
+
+    let prompt = match args.preset.unwrap_or(Preset::Conventional) {
+        Preset::Conventional | Preset::ConventionalWithBody => load_system_prompt(),
+        Preset::Gitmoji => load_gitmoji_prompt(),
+        Preset::Plain => load_plain_prompt(),
+        Preset::Jira => load_jira_prompt(),
+    };
+
+    Ok(prompt.to_string())
 }
 
-async fn generate_commit_message(model: &str, temperature: f32) -> Result<String> {
-    // Get OpenAI API key from environment variables
-    let api_key = env::var("OPENAI_API_KEY").context("OPENAI_API_KEY not found. Please set it in your .env file or environment variables.")?;
-
-    // Get git diff
-    let diff = get_git_diff()?;
-
-    // Load system prompt
-    let system_prompt = load_system_prompt();
-
-    // Create OpenAI API client
-    let client = Client::new();
-
-    // Create the request body
-    let request = OpenAIRequest {
-        model: model.to_string(),
-        messages: vec![
-            Message {
-                role: "system".to_string(),
-                content: system_prompt.to_string(),
-            },
-            Message {
-                role: "user".to_string(),
-                content: format!("Analyze this diff and generate a conventional commit message. Pay attention to what the code actually does, not just file names:\n\n{}", diff),
-            },
-        ],
-        temperature,
-    };
-
-    // Send request to OpenAI API
-    let response = client.post("https://api.openai.com/v1/chat/completions")
-        .header("Authorization", format!("Bearer {}", api_key))
-        .header("Content-Type", "application/json")
-        .json(&request)
-        .send()
-        .await
-        .context("Failed to send request to OpenAI API")?;
+fn load_explain_prompt() -> &'static str {
+    include_str!("explain_prompt.txt")
+}
+
+fn load_pr_prompt() -> &'static str {
+    include_str!("pr_prompt.txt")
+}
 
-    // Check if response status is successful
-    if !response.status().is_success() {
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(anyhow::anyhow!("API request failed: {}", error_text));
+#[tokio::main]
+async fn main() {
+    exit_on_broken_pipe();
+
+    // Dropping `run` on Ctrl-C lets a parked index put itself back
+    let result = tokio::select! {
+        result = run() => Some(result),
+        _ = tokio::signal::ctrl_c() => None,
+    };
+
+    match result {
+        None => std::process::exit(130),
+        Some(Err(err)) if !err.is::<PromptShown>() => std::process::exit(report_error(&err)),
+        Some(_) => {}
     }
+}
 
-    // Parse response
-    let response_body = response.json::<OpenAIResponse>()
-        .await
-        .context("Failed to parse OpenAI API response")?;
+/// `println!` panics once a reader such as `head` closes the pipe. Exit
+/// quietly instead, like other command-line tools. SIGPIPE itself stays
+/// ignored, so a validator or hook that never reads its stdin can't kill us.
+fn exit_on_broken_pipe() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let message = info.payload().downcast_ref::<String>().map(String::as_str).unwrap_or_default();
+        if message.starts_with("failed printing to stdout") && message.contains("Broken pipe") {
+            std::process::exit(0);
+        }
+        default_hook(info);
+    }));
+}
 
-    // Check for API errors
-    if let Some(error) = response_body.error {
-        return Err(anyhow::anyhow!("OpenAI API error: {}", error.message));
+/// Print a categorized, actionable error and return the exit code for it.
+fn report_error(err: &anyhow::Error) -> i32 {
+    if ERROR_FORMAT.get() == Some(&ErrorFormat::Ci) {
+        let (label, hint, code) = match err.downcast_ref::<GaiError>() {
+            Some(category) => (category.label(), format!("\n{}", category.hint()), category.exit_code()),
+            None => ("Error", String::new(), 1),
+        };
+        eprintln!("{}", ci_annotation(label, &format!("{:#}{}", err, hint)));
+        return code;
     }
 
-    // Extract commit message from response
-    let commit_message = response_body.choices
-        .first()
-        .context("No choices in response")?
-        .message
-        .content
-        .clone();
+    match err.downcast_ref::<GaiError>() {
+        Some(category) => {
+            eprintln!("❌ {}: {:#}", category.label(), err);
+            eprintln!("   {}", category.hint());
+            category.exit_code()
+        }
+        None => {
+            eprintln!("❌ Error: {:#}", err);
+            1
+        }
+    }
+}
 
-    // Clean up the message (remove quotes if present, trim whitespace)
-    let clean_message = commit_message
-        .trim()
-        .trim_matches('"')
-        .to_string();
+/// A GitHub Actions `::error` workflow command, escaped so newlines, `%`
+/// and the title's `:` and `,` survive.
+fn ci_annotation(title: &str, message: &str) -> String {
+    let escape = |text: &str| text.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A");
+    let title = escape(title).replace(':', "%3A").replace(',', "%2C");
 
-    Ok(clean_message)
+    format!("::error title=gitai {}::{}", title, escape(message))
 }
 
-fn get_git_diff() -> Result<String> {
-    // Check if we're in a git repository
-    let git_check = Command::new("git")
-        .args(["rev-parse", "--is-inside-work-tree"])
-        .output()
-        .context("Failed to execute git command. Is git installed?")?;
+async fn run() -> Result<()> {
+    // Load environment variables from .env file
+    dotenv().ok();
 
-    if !git_check.status.success() {
-        return Err(anyhow::anyhow!("Not inside a git repository"));
+    // Parse command line arguments, keeping the matches so config files
+    // only fill in values that weren't given explicitly
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    ERROR_FORMAT.get_or_init(|| args.format);
+
+    // Every git command and the repository's config then follow the
+    // worktree in DIR, just as they follow the one gitai is started in
+    if let Some(dir) = &args.directory {
+        env::set_current_dir(dir).with_context(|| GaiError::Io(format!("Cannot change to directory {}", dir.display())))?;
     }
 
-    // Execute git diff --staged command and capture output
-    let output = Command::new("git")
-        .args(["diff", "--staged"])
-        .output()
-        .context("Failed to execute git diff command")?;
+    let config = Config::load()?;
+    config.apply(&mut args, &matches)?;
+    args.config = config;
 
-    // Convert output to string
-    let diff = String::from_utf8(output.stdout)
-        .context("Failed to parse git diff output as UTF-8")?;
+    REQUEST_SLOTS.get_or_init(|| Semaphore::new(args.concurrency));
 
-    if diff.is_empty() {
-        return Err(anyhow::anyhow!("No staged changes found. Use 'git add' to stage your changes."));
+    // From then on it's --since the point where the branch left its upstream
+    if args.changed_since_pull {
+        args.since = Some(upstream_fork_point()?);
     }
 
-    Ok(diff)
-}
+    // Responses are appended as they arrive, so start from an empty file
+    if let Some(path) = &args.dump_response {
+        fs::write(path, "").with_context(|| GaiError::Io(format!("Failed to create {}", path.display())))?;
+    }
 
-fn create_commit(message: &str) -> Result<()> {
-    let output = Command::new("git")
-        .args(["commit", "-m", message])
-        .output()
-        .context("Failed to execute git commit command")?;
+    match &args.command {
+        Some(Commands::SelfUpdate { check_only }) => return update::self_update(*check_only).await,
+        Some(Commands::History { grep }) => return print_history(grep.as_deref()),
+        None => {}
+    }
 
-    if !output.status.success() {
-        let error = String::from_utf8_lossy(&output.stderr);
-        return Err(anyhow::anyhow!("Commit failed: {}", error));
+    if let Some(path) = args.rebase_editor.clone() {
+        return edit_rebase_message(&mut args, &path).await;
     }
 
-    Ok(())
+    if args.record_commit {
+        return history::record_commit();
+    }
+
+    if args.capabilities {
+        println!("{}", serde_json::to_string_pretty(&capabilities())?);
+        return Ok(());
+    }
+
+    if args.install_hook {
+        let path = history::install_hook()?;
+        println!("✅ Installed post-commit hook at {}", path.display());
+        if !args.history {
+            println!("ℹ️ Messages are only logged with history = true in the config, or with --history");
+        }
+        return Ok(());
+    }
+
+    if let Some(path) = &args.validate_only {
+        return validate_only(&args, path);
+    }
+
+    if args.verify_model {
+        // On its own, --verify-model is just the check
+        let alone = !Args { verify_model: false, ..args.clone() }.has_action();
+        verify_model(&args).await?;
+        if alone {
+            if !args.quiet {
+                println!("✅ {} is available from {:?}", args.model, args.provider);
+            }
+            return Ok(());
+        }
+    }
+
+    if args.watch {
+        return watch_index(&args).await;
+    }
+
+    if args.patch {
+        stage_patch()?;
+        // On its own, --patch means "stage, then show me a message"
+        if !args.has_action() {
+            args.generate = true;
+        }
+    }
+
+    if args.commit_all {
+        // Without a terminal to confirm on, fail before staging or spending a request
+        if !args.yes && !std::io::stdin().is_terminal() {
+            return Err(GaiError::Config("--commit-all asks before committing; pass --yes to commit without asking".to_string()).into());
+        }
+        git(&["add", "-A"])?;
+        args.commit = true;
+    }
+
+    // Asked once for the whole run, before candidates or a sweep send
+    // their requests all at once; a pull request's diff is fetched first
+    if args.pr_url.is_none() {
+        confirm_run_cost(&args, &estimated_diff(&args))?;
+    }
+
+    // Check before generating so a refusal doesn't waste a request
+    if (args.commit || args.interactive || args.auto_split || args.split_by_package) && !args.force {
+        confirm_detached_head()?;
+    }
+
+    if (args.commit || args.interactive || args.auto_split || args.split_by_package) && args.require_clean_worktree {
+        check_partially_staged()?;
+    }
+
+    if args.prewarm {
+        generate_commit_message(&args).await?;
+        if !args.quiet {
+            println!("🔥 Cached a message for the staged changes");
+        }
+        return Ok(());
+    }
+
+    if !args.temperature_sweep.is_empty() {
+        // --concurrency keeps a long sweep from tripping rate limits
+        let results = futures::future::join_all(
+            args.temperature_sweep
+                .iter()
+                .map(|&temperature| generate_commit_message_at(&args, Some(temperature))),
+        )
+        .await;
+
+        for (temperature, result) in args.temperature_sweep.iter().zip(results) {
+            println!("🌡️ {}:", temperature);
+            match result {
+                Ok(message) => println!("{}\n", message),
+                Err(e) if e.is::<PromptShown>() => return Err(e),
+                Err(e) => eprintln!("⚠️ {:#}\n", e),
+            }
+        }
+        return Ok(());
+    }
+
+    if !args.batch.is_empty() {
+        let messages = generate_batch_messages(&args).await?;
+
+        for (path, message) in args.batch.iter().zip(&messages) {
+            println!("📝 {}:", path.display());
+            println!("{}\n", message);
+        }
+    } else if let Some(url) = &args.pr_url {
+        let diff = filter_do_not_send(&args, &github::fetch_pr_diff(url).await?)?;
+        let diff = match args.diff_file_limit {
+            Some(limit) => limit_file_diffs(&diff, limit),
+            None => diff,
+        };
+        let diff = fit_diff(&args, diff, || Ok(String::new()))?;
+        confirm_run_cost(&args, &diff)?;
+        let text = if args.explain { explain(&args, &diff).await? } else { remote_diff_message(&args, &diff).await? };
+        println!("{}", text);
+    } else if args.interactive {
+        commit_interactively(&args).await?;
+    } else if args.auto_split {
+        commit_split_by_type(&args).await?;
+    } else if args.split_by_package {
+        commit_split_by_package(&args).await?;
+    } else if args.pr {
+        // Printed without decoration so it can be piped into `gh pr create --body-file`
+        let description = generate_pr_description(&args).await?;
+        println!("{}", description);
+    } else if args.fix_scope || args.fix_type {
+        // Undecorated so it can be piped straight into `git commit -F -`
+        let message = fix_message_prefix(&args).await?;
+        println!("{}", message);
+    } else if args.explain {
+        let explanation = explain_diff(&args).await?;
+
+        println!("🔍 Explanation of staged changes:");
+        println!("{}", explanation);
+    } else if args.generate || args.commit {
+        // Generate commit message
+        let commit_message = generate_and_record(&args).await?;
+
+        // Repeated subjects make history confusing, e.g. after a run of small fixes
+        let previous = git(&["log", "-1", "--pretty=%s"]).unwrap_or_default();
+        if commit_message.lines().next() == Some(previous.trim()) {
+            eprintln!("⚠️ This subject is identical to the previous commit's; consider making it more specific.");
+        }
+
+        let mut score = None;
+        if args.score || args.min_score.is_some() {
+            score = Some(score_message(&args, &commit_message).await?);
+        }
+        if args.score
+            && let Some(score) = score
+        {
+            // Keep stdout to just the message in quiet mode
+            if args.quiet {
+                eprintln!("📊 Confidence: {}/10", score);
+            } else {
+                println!("📊 Confidence: {}/10", score);
+            }
+        }
+
+        if let (Some(min_score), Some(score)) = (args.min_score, score)
+            && score < min_score
+            && !args.print_command
+        {
+            confirm_low_score(&commit_message, score, min_score)?;
+        }
+
+        if args.copy {
+            // Not having a clipboard shouldn't cost the generated message
+            match copy_to_clipboard(&commit_message) {
+                Ok(()) if !args.quiet => eprintln!("📋 Copied to clipboard"),
+                Ok(()) => {}
+                Err(e) => eprintln!("⚠️ {}", e),
+            }
+        }
+
+        if args.print_command {
+            // Quoted for a POSIX shell so it can be pasted as is
+            let command = commit_command(&args, &commit_message);
+            let words = std::iter::once(command.get_program())
+                .chain(command.get_args())
+                .map(|word| shell_quote(&word.to_string_lossy()))
+                .collect::<Vec<_>>();
+            println!("{}", words.join(" "));
+        } else if args.commit {
+            // Everything was staged, so let the user see what it's described as first
+            if args.commit_all && !args.yes {
+                confirm_commit_all(&commit_message)?;
+            }
+
+            // Use the generated message to create a commit
+            create_commit(&args, &commit_message)?;
+            add_generation_note(&args);
+            if let Some(template) = &args.output_template {
+                println!("{}", render_template(template, &commit_message));
+            } else if !args.quiet {
+                println!("✅ Committed with message: \"{}\"", commit_message);
+            }
+
+            if args.push {
+                push_current_branch()?;
+                if !args.quiet {
+                    println!("🚀 Pushed");
+                }
+            }
+        } else if let Some(template) = &args.output_template {
+            println!("{}", render_template(template, &commit_message));
+        } else if args.quiet {
+            println!("{}", commit_message);
+        } else {
+            // Just print the message
+            println!("📝 Generated commit message:");
+            println!("{}", commit_message);
+            if args.config.show_hint.unwrap_or(true) {
+                println!("\nTo use this message:");
+                println!("git commit -m \"{}\"", commit_message);
+            }
+        }
+    } else if !std::io::stdout().is_terminal() {
+        return Err(GaiError::Config(
+            "No action given; specify --generate or --commit, or set pipe_action = \"generate\" in your config".to_string(),
+        ).into());
+    } else {
+        println!("🤖 gitai - AI Powered Git Commit Messages");
+        println!("Use --generate (-g) to create a commit message");
+        println!("Use --commit (-c) to commit with the generated message");
+        println!("Use --explain (-e) to explain the staged changes");
+        println!("\nRun 'gitai --help' for more options");
+    }
+
+    Ok(())
+}
+
+/// Fill --output-template's `{field}` placeholders from the parts of a
+/// conventional commit message. Unknown placeholders are left as they are.
+fn render_template(template: &str, message: &str) -> String {
+    let (rest, footers) = lint::split_footers(message);
+    let (subject, body) = rest.split_once('\n').unwrap_or((rest, ""));
+    let parsed = lint::parse_subject(subject);
+
+    let field = |name: &str| -> Option<String> {
+        Some(match name {
+            "type" => parsed.as_ref().map(|s| s.kind).unwrap_or_default().to_string(),
+            "scope" => parsed.as_ref().and_then(|s| s.scope).unwrap_or_default().to_string(),
+            "breaking" => parsed.as_ref().is_some_and(|s| s.breaking).to_string(),
+            "subject" => subject.to_string(),
+            "description" => parsed.as_ref().map_or(subject, |s| s.description).to_string(),
+            "body" => body.trim().to_string(),
+            "footers" => footers.join("\n"),
+            "message" => message.to_string(),
+            _ => return None,
+        })
+    };
+
+    // One pass, so text inside a field is never taken for a placeholder
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let value = rest[start..].find('}').and_then(|end| Some((field(&rest[start + 1..start + end])?, end)));
+        match value {
+            Some((value, end)) => {
+                rendered.push_str(&value);
+                rest = &rest[start + end + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    rendered.push_str(rest);
+
+    rendered
+}
+
+async fn generate_commit_message(args: &Args) -> Result<String> {
+    generate_commit_message_at(args, None).await
+}
+
+/// Generate a message, at `temperature` if given instead of the usual one.
+async fn generate_commit_message_at(args: &Args, temperature: Option<f32>) -> Result<String> {
+    let (diff, path_tokens) = get_git_diff(args)?;
+    describe_diff(args, temperature, &diff, &path_tokens).await
+}
+
+/// Write a message for `diff`, as returned by `get_git_diff`.
+async fn describe_diff(args: &Args, temperature: Option<f32>, diff: &str, path_tokens: &[(String, String)]) -> Result<String> {
+    // Only --allow-empty lets an empty diff through; there's nothing to describe
+    if diff.is_empty() {
+        return empty_commit_message(args);
+    }
+
+    if !args.no_deps_message
+        && let Some(message) = dependency_message(args)?
+    {
+        return Ok(message);
+    }
+
+    if args.local {
+        return local_message(args, diff);
+    }
+
+    let mut temperature = temperature.unwrap_or_else(|| resolve_temperature(args.temperature, args.temperature_auto, diff));
+
+    // Load system prompt, narrowed to the allowed types if configured
+    let mut system_prompt = preset_system_prompt(args)?;
+    if let Some(types) = &args.config.allowed_types {
+        system_prompt.push_str(&format!(
+            "\n\nALLOWED TYPES:\n- This repository only allows these types: {}\n- Never use any other type, even if another one would fit better",
+            types.join(", ")
+        ));
+    }
+    let kinds = args.config.type_verbs.iter().flat_map(|verbs| verbs.keys()).chain(args.type_verbs.iter().map(|(kind, _)| kind));
+    let mut verbs = Vec::new();
+    for kind in kinds {
+        if let Some(verb) = type_verb(args, kind)
+            && !verbs.iter().any(|(known, _)| known == kind)
+        {
+            verbs.push((kind.clone(), verb));
+        }
+    }
+    if !verbs.is_empty() {
+        verbs.sort();
+        system_prompt.push_str("\n\nPREFERRED VERBS:");
+        for (kind, verb) in verbs {
+            system_prompt.push_str(&format!("\n- Start the description of a {} commit with \"{}\" when it fits", kind, verb));
+        }
+    }
+    if let Some(detail) = args.detail {
+        system_prompt.push_str(&format!("\n\nLEVEL OF DETAIL:\n- {}", detail.instruction()));
+    }
+    // Trivial changes don't need a body, even when one was asked for
+    let small = args.no_body_if_small.is_some_and(|max| changed_line_count(diff) <= max);
+    if (args.body || args.preset == Some(Preset::ConventionalWithBody)) && !small {
+        system_prompt.push_str("\n\nBODY:\n- After the subject line, add a blank line and a body of \"- \" bullet points saying what changed and why");
+        if let Some(max) = args.max_bullets {
+            system_prompt.push_str(&format!("\n- Use at most {} bullet points; keep only the most important ones", max));
+        }
+    }
+
+    let template = args.user_prompt.as_deref().unwrap_or(DEFAULT_USER_PROMPT);
+    if !template.contains("{diff}") {
+        return Err(GaiError::Config("The user prompt template must contain a {diff} placeholder".to_string()).into());
+    }
+
+    // Hints go right before the diff so custom templates keep them too
+    let mut diff_block = String::new();
+    let hints = prompt_hints(args, diff, path_tokens)?;
+    if !hints.is_empty() {
+        diff_block.push_str("Hints:");
+        for hint in &hints {
+            diff_block.push_str(&format!("\n- {}", hint));
+        }
+        diff_block.push_str("\n\n");
+    }
+    diff_block.push_str(diff);
+
+    // Working out what the diff does first makes the subject easier to get right
+    if args.think {
+        let summary = summarize_diff(args, temperature, diff).await?;
+        if args.verbose {
+            eprintln!("🧠 Summary of the diff:\n{}\n", summary);
+        }
+        diff_block.push_str(&format!("\n\nSummary of what this diff does:\n{}", summary));
+    }
+
+    let content = template.replace("{diff}", &diff_block);
+
+    let mut messages = vec![
+        Message {
+            role: "system".to_string(),
+            content: system_prompt,
+        },
+        Message {
+            role: "user".to_string(),
+            content,
+        },
+    ];
+
+    let rules = message_rules(args)?;
+    let response_format = args.json_schema.then(|| commit_message_schema(args));
+
+    // Ask the model to fix its own message until it satisfies the rules
+    let mut attempt = 0;
+    loop {
+        let reply = send_chat_request_with_format(args, temperature, messages.clone(), response_format.clone()).await?;
+        let commit_message = match response_format {
+            Some(_) => serde_json::from_str::<StructuredMessage>(&reply)
+                .context(GaiError::Provider("The model's reply doesn't match the commit message schema".to_string()))?
+                .assemble(),
+            None => clean_message(&reply),
+        };
+        // Models don't always respect the limit, so enforce it here too
+        let commit_message = match args.max_bullets {
+            Some(max) => limit_bullets(&commit_message, max),
+            None => commit_message,
+        };
+
+        // The team's own validator runs once the built-in rules are satisfied
+        let mut problems = rules.check(&commit_message);
+        let mut max_attempts = MAX_FIX_ATTEMPTS;
+        if problems.is_empty() {
+            match validation_failure(args, &commit_message)? {
+                Some(output) => {
+                    problems.push(output);
+                    max_attempts = args.config.validate_retries.unwrap_or(MAX_FIX_ATTEMPTS);
+                }
+                None => return Ok(restore_tokens(&commit_message, path_tokens)),
+            }
+        }
+
+        if attempt >= max_attempts {
+            return Err(GaiError::Provider(format!(
+                "The generated message \"{}\" still breaks the commit rules after {} retries: {}",
+                commit_message,
+                attempt,
+                problems.join("; ")
+            )).into());
+        }
+        attempt += 1;
+
+        // A more deterministic reply is more likely to follow the rules
+        if args.retry_different_temperature {
+            temperature /= 2.0;
+        }
+
+        messages.push(Message {
+            role: "assistant".to_string(),
+            content: reply,
+        });
+        messages.push(Message {
+            role: "user".to_string(),
+            content: format!(
+                "That message breaks these rules: {}. Reply with only a corrected commit message.",
+                problems.join("; ")
+            ),
+        });
+    }
+}
+
+/// Subject length --auto-pick aims for when commitlint sets no maximum.
+const TARGET_SUBJECT_LENGTH: usize = 50;
+
+/// Generate --candidates messages at once for the same diff, and return
+/// the one the user, or --auto-pick, chooses along with the diff as sent.
+async fn generate_candidates(args: &Args) -> Result<(String, String)> {
+    let (diff, path_tokens) = get_git_diff(args)?;
+    if args.candidates == 1 {
+        return Ok((describe_diff(args, None, &diff, &path_tokens).await?, diff));
+    }
+
+    let results = futures::future::join_all((0..args.candidates).map(|_| describe_diff(args, None, &diff, &path_tokens))).await;
+
+    let mut candidates = Vec::new();
+    let mut first_error = None;
+    for result in results {
+        match result {
+            Ok(message) if !candidates.contains(&message) => candidates.push(message),
+            Ok(_) => {}
+            Err(e) if e.is::<PromptShown>() => return Err(e),
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    if candidates.is_empty() {
+        return Err(first_error.expect("at least one candidate was requested"));
+    }
+
+    // Without a terminal there is nobody to ask
+    if args.auto_pick || !std::io::stdin().is_terminal() {
+        let rules = message_rules(args)?;
+        let target = rules.header_max_length.map_or(TARGET_SUBJECT_LENGTH, |max| max.min(TARGET_SUBJECT_LENGTH));
+
+        let rank = |message: &String| {
+            let subject = message.lines().next().unwrap_or_default();
+            (
+                rules.check(message).len(),
+                lint::parse_subject(subject).is_none(),
+                strip_emoji(message) != *message,
+                subject.chars().count().abs_diff(target),
+            )
+        };
+        let best = candidates.iter().min_by_key(|message| rank(message)).cloned().expect("candidates is not empty");
+        return Ok((best, diff));
+    }
+
+    let subjects = candidates.iter().map(|message| message.lines().next().unwrap_or_default()).collect::<Vec<_>>();
+    let choice = Select::new()
+        .with_prompt("Pick a commit message")
+        .items(&subjects)
+        .default(0)
+        .interact()
+        .context(GaiError::Io("Failed to read the selection".to_string()))?;
+
+    Ok((candidates.swap_remove(choice), diff))
+}
+
+/// Generate a message that is safe to commit and add it to the history log.
+async fn generate_and_record(args: &Args) -> Result<String> {
+    // A message given up front skips the API, but not the formatting below
+    let supplied = args.message.as_ref().filter(|_| !args.regenerate);
+    let (mut commit_message, sent_diff) = match supplied {
+        Some(message) => (message.trim().to_string(), None),
+        None => {
+            let (message, diff) = generate_candidates(args).await?;
+            (message, Some(diff))
+        }
+    };
+
+    if let Some(command) = &args.config.postprocess_command {
+        commit_message = clean_message(&run_shell(command, &commit_message)?);
+    }
+
+    commit_message = run_plugins(args, commit_message, sent_diff.as_deref())?;
+
+    // Many commitlint setups reject emoji, and models add them unprompted
+    if supplied.is_none() && !args.allow_emoji && args.preset != Some(Preset::Gitmoji) {
+        commit_message = strip_emoji(&commit_message);
+    }
+
+    if supplied.is_none() && args.spellcheck {
+        commit_message = spellcheck(args, &commit_message)?;
+    }
+
+    if supplied.is_none()
+        && let Some(kind) = lint::parse_subject(commit_message.lines().next().unwrap_or_default()).map(|s| s.kind.to_string())
+        && let Some(verb) = type_verb(args, &kind)
+    {
+        commit_message = apply_type_verb(&commit_message, verb);
+    }
+
+    if let Some(case) = args.case {
+        commit_message = apply_subject_case(&commit_message, case);
+    }
+
+    // Generated messages were checked already, and fixed by the model if need be
+    if supplied.is_some() {
+        let problems = message_rules(args)?.check(&commit_message);
+        if !problems.is_empty() {
+            return Err(GaiError::Config(format!("The message breaks the commit rules: {}", problems.join("; "))).into());
+        }
+        if let Some(output) = validation_failure(args, &commit_message)? {
+            return Err(GaiError::Config(format!("validate_command rejected the message: {}", output)).into());
+        }
+    }
+
+    // Never hand git an empty message; it fails with a confusing error
+    if commit_message.trim().is_empty() {
+        return Err(GaiError::Provider("The model returned an empty message. Try again or use a different model.".to_string()).into());
+    }
+
+    // Keep one footer block: the model's, then trailers of the message being
+    // regenerated, then ours
+    let (body, mut trailers) = lint::split_footers(&commit_message);
+    let previous = args.message.as_deref().map(lint::split_footers).map(|(_, footers)| footers).unwrap_or_default();
+    for footer in previous.into_iter().chain(footers(args)?) {
+        if !trailers.contains(&footer) {
+            trailers.push(footer);
+        }
+    }
+    if !trailers.is_empty() {
+        commit_message = format!("{}\n\n{}", body, trailers.join("\n"));
+    }
+
+    if args.ascii_only {
+        commit_message = to_ascii(&commit_message);
+    } else if args.normalize_punctuation {
+        commit_message = normalize_punctuation(&commit_message);
+    }
+
+    // The post-commit hook fills in the SHA, so record before committing;
+    // the log lives in the git directory, which a diff_command may not have
+    if supplied.is_none()
+        && args.history
+        && (args.diff_command.is_none() || repository_root().is_ok())
+        && let Err(e) = history::record(&args.model, &commit_message)
+    {
+        eprintln!("⚠️ Failed to write history log: {}", e);
+    }
+
+    Ok(commit_message)
+}
+
+/// Fix typos above the footers of `message`. Words in the diff are taken
+/// to be identifiers, not typos.
+fn spellcheck(args: &Args, message: &str) -> Result<String> {
+    let diff = match &args.diff_command {
+        Some(command) => command_diff(args, command)?,
+        None => git_diff_report(args, "--unified=0")?,
+    };
+    let words = diff_words(&diff);
+    let (body, footers) = lint::split_footers(message);
+    let (body, fixes) = spelling::correct(body, |word| words.contains(&word.to_lowercase()));
+
+    if args.verbose {
+        for (typo, correction) in &fixes {
+            eprintln!("✏️ Spelling: {} → {}", typo, correction);
+        }
+    }
+
+    if footers.is_empty() {
+        return Ok(body);
+    }
+    Ok(format!("{}\n\n{}", body, footers.join("\n")))
+}
+
+/// Every identifier in `diff`, lowercased, along with the words inside
+/// snake_case and camelCase ones, so a misspelling is only kept when the
+/// code really uses it and not when it happens to sit inside a longer word.
+fn diff_words(diff: &str) -> HashSet<String> {
+    let mut words = HashSet::new();
+    for identifier in diff.split(|c: char| !(c.is_alphanumeric() || c == '_')).filter(|token| !token.is_empty()) {
+        words.insert(identifier.to_lowercase());
+
+        for part in identifier.split('_').filter(|part| !part.is_empty()) {
+            let mut word = String::new();
+            let mut previous_lower = false;
+            for c in part.chars() {
+                if c.is_uppercase() && previous_lower {
+                    words.insert(std::mem::take(&mut word).to_lowercase());
+                }
+                previous_lower = c.is_lowercase() || c.is_ascii_digit();
+                word.push(c);
+            }
+            words.insert(word.to_lowercase());
+        }
+    }
+    words
+}
+
+/// Verbs that only differ in style, so the configured one can stand in for
+/// them; anything more specific, like "remove", is left alone.
+const GENERIC_VERBS: &[&str] = &[
+    "add", "adds", "added", "create", "introduce", "implement", "fix", "fixes", "fixed", "resolve", "resolves",
+    "correct", "repair", "update", "updates", "updated", "change", "changes", "modify", "adjust",
+];
+
+/// The --type-verb or `type_verbs` verb for commits of type `kind`.
+fn type_verb<'a>(args: &'a Args, kind: &str) -> Option<&'a str> {
+    let verb = match args.type_verbs.iter().rev().find(|(mapped, _)| mapped == kind) {
+        Some((_, verb)) => verb,
+        None => args.config.type_verbs.as_ref()?.get(kind)?,
+    };
+    Some(verb.as_str()).filter(|verb| !verb.is_empty())
+}
+
+/// Swap a generic first word of the subject's description for `verb`,
+/// keeping its capitalization.
+fn apply_type_verb(message: &str, verb: &str) -> String {
+    let (subject, rest) = message.split_once('\n').map_or((message, None), |(subject, rest)| (subject, Some(rest)));
+    let Some(colon) = subject.find(':') else {
+        return message.to_string();
+    };
+
+    let (prefix, description) = subject.split_at(colon + 1);
+    let description = description.trim_start();
+    let (first, tail) = description.split_once(' ').unwrap_or((description, ""));
+    if !GENERIC_VERBS.contains(&first.to_lowercase().as_str()) {
+        return message.to_string();
+    }
+
+    let verb = match first.chars().next() {
+        Some(c) if c.is_uppercase() => verb.chars().next().map(|v| v.to_uppercase().chain(verb.chars().skip(1)).collect()).unwrap_or_default(),
+        _ => verb.to_string(),
+    };
+    let subject = if tail.is_empty() { format!("{} {}", prefix, verb) } else { format!("{} {} {}", prefix, verb, tail) };
+
+    match rest {
+        Some(rest) => format!("{}\n{}", subject, rest),
+        None => subject,
+    }
+}
+
+/// Re-case the first letter of the subject's description, leaving any
+/// `type(scope):` prefix and the body untouched.
+fn apply_subject_case(message: &str, case: SubjectCase) -> String {
+    let (subject, rest) = message.split_once('\n').map_or((message, None), |(subject, rest)| (subject, Some(rest)));
+
+    // Without a conventional prefix the whole subject is the description
+    let start = match lint::parse_subject(subject) {
+        Some(_) => subject.find(':').map_or(0, |colon| colon + 1),
+        None => 0,
+    };
+    let (prefix, description) = subject.split_at(start);
+
+    // Skip leading emoji and punctuation so "✨ add" still becomes "✨ Add"
+    let mut recased = prefix.to_string();
+    let mut done = false;
+    for c in description.chars() {
+        if !done && c.is_alphabetic() {
+            match case {
+                SubjectCase::Lower => recased.extend(c.to_lowercase()),
+                SubjectCase::Sentence => recased.extend(c.to_uppercase()),
+                SubjectCase::Preserve => recased.push(c),
+            }
+            done = true;
+        } else {
+            recased.push(c);
+        }
+    }
+
+    match rest {
+        Some(rest) => format!("{}\n{}", recased, rest),
+        None => recased,
+    }
+}
+
+/// Replace typographic punctuation with its ASCII equivalent, keeping every
+/// other character.
+fn normalize_punctuation(message: &str) -> String {
+    message
+        .chars()
+        .map(|c| ascii_punctuation(c).map_or_else(|| c.to_string(), str::to_string))
+        .collect()
+}
+
+fn ascii_punctuation(c: char) -> Option<&'static str> {
+    match c {
+        '‘' | '’' | '′' => Some("'"),
+        '“' | '”' | '″' => Some("\""),
+        '–' | '—' | '−' => Some("-"),
+        '…' => Some("..."),
+        '→' => Some("->"),
+        '\u{a0}' => Some(" "),
+        _ => None,
+    }
+}
+
+/// Drop emoji from `message`, along with the space each one leaves behind.
+fn strip_emoji(message: &str) -> String {
+    let is_emoji = |c: char| {
+        matches!(c as u32,
+            0x1F000..=0x1FAFF // pictographs, emoticons, flags and the like
+            | 0x2600..=0x27BF // miscellaneous symbols and dingbats
+            | 0x2B50 | 0x2B55 | 0x231A | 0x231B | 0x23E9..=0x23FA
+            | 0xFE0F | 0x200D | 0x20E3 // presentation selector, joiner, keycap
+            | 0xE0020..=0xE007F) // tag sequences
+    };
+
+    let mut stripped = String::with_capacity(message.len());
+    let mut dropped = false;
+    for c in message.chars() {
+        if is_emoji(c) {
+            dropped = true;
+            continue;
+        }
+        if c == ' ' && dropped && (stripped.is_empty() || stripped.ends_with([' ', '\n'])) {
+            continue;
+        }
+        dropped = false;
+        stripped.push(c);
+    }
+
+    stripped.lines().map(|line| line.trim_end()).collect::<Vec<_>>().join("\n")
+}
+
+/// Transliterate typographic punctuation and drop everything else outside
+/// ASCII, for terminals and tools that mangle it.
+fn to_ascii(message: &str) -> String {
+    let mut ascii = String::with_capacity(message.len());
+    for c in message.chars() {
+        match ascii_punctuation(c) {
+            Some(replacement) => ascii.push_str(replacement),
+            None if c.is_ascii() => ascii.push(c),
+            None => {}
+        }
+    }
+
+    // Dropping a leading emoji leaves a stray space, as in "✨ Add login"
+    ascii.lines().map(|line| line.trim_end()).collect::<Vec<_>>().join("\n").trim_start().to_string()
+}
+
+/// Trailers appended to every generated message, in the order git shows them.
+fn footers(args: &Args) -> Result<Vec<String>> {
+    let mut footers = Vec::new();
+
+    for issue in &args.closes {
+        let issue = match issue.as_str() {
+            "auto" => issue_from_branch()?,
+            issue => issue.trim_start_matches('#').to_string(),
+        };
+        footers.push(format!("Closes #{}", issue));
+    }
+
+    if args.stat_footer {
+        footers.push(stat_footer(args)?);
+    }
+
+    if let Some(path) = &args.config.trailer_template {
+        let template = fs::read_to_string(path)
+            .with_context(|| GaiError::Config(format!("Failed to read trailer template {}", path.display())))?;
+        for line in template.lines() {
+            // Indented lines continue the trailer above them
+            if line.starts_with([' ', '\t'])
+                && !line.trim().is_empty()
+                && let Some(last) = footers.last_mut()
+            {
+                last.push('\n');
+                last.push_str(line);
+            } else if !line.trim().is_empty() && !line.starts_with('#') {
+                footers.push(line.trim_end().to_string());
+            }
+        }
+    }
+
+    Ok(footers)
+}
+
+/// Run `git diff <option>` over the same revisions and paths as the diff
+/// sent to the model, intent-to-add files included.
+fn git_diff_report(args: &Args, option: &str) -> Result<String> {
+    let pathspecs = pathspecs(args);
+    let report = |revisions: &[String]| {
+        let mut command = vec!["diff", option];
+        command.extend(revisions.iter().map(String::as_str));
+        command.push("--");
+        command.extend(pathspecs.iter().map(String::as_str));
+        git(&command)
+    };
+
+    let mut output = report(&diff_revisions(args))?;
+    if diffs_index(args) {
+        output.push_str(&report(&[INTENT_TO_ADD_FILTER.to_string()])?);
+    }
+
+    Ok(output)
+}
+
+/// Summarize `git diff --shortstat`, e.g. `Changes: 3 files, +42/-5`.
+fn stat_footer(args: &Args) -> Result<String> {
+    let stat = git_diff_report(args, "--shortstat")?;
+
+    // " 3 files changed, 42 insertions(+), 5 deletions(-)", with zero counts left out
+    // Intent-to-add files are counted on a line of their own
+    let count = |kind: &str| {
+        stat.lines()
+            .flat_map(|line| line.split(','))
+            .filter(|part| part.contains(kind))
+            .filter_map(|part| part.split_whitespace().next()?.parse::<usize>().ok())
+            .sum::<usize>()
+    };
+
+    let files = count("changed");
+    Ok(format!(
+        "Changes: {} file{}, +{}/-{}",
+        files,
+        if files == 1 { "" } else { "s" },
+        count("insertion"),
+        count("deletion")
+    ))
+}
+
+/// A Jira issue key such as `PROJ-123` in the current branch name.
+fn jira_key_from_branch() -> Option<String> {
+    let branch = git(&["branch", "--show-current"]).ok()?;
+
+    branch
+        .trim()
+        .split(['/', '_', '.'])
+        .find_map(|part| {
+            let (project, rest) = part.split_once('-')?;
+            let number = rest.split('-').next()?;
+            let valid = !project.is_empty()
+                && project.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+                && project.starts_with(|c: char| c.is_ascii_uppercase())
+                && !number.is_empty()
+                && number.chars().all(|c| c.is_ascii_digit());
+            valid.then(|| format!("{}-{}", project, number))
+        })
+}
+
+/// The first number in the current branch name, e.g. 123 in `fix/123-login`.
+fn issue_from_branch() -> Result<String> {
+    let branch = git(&["branch", "--show-current"])?;
+    let branch = branch.trim();
+
+    branch
+        .split(|c: char| !c.is_ascii_digit())
+        .find(|token| !token.is_empty())
+        .map(str::to_string)
+        .ok_or_else(|| GaiError::Git(format!("No issue number found in the branch name '{}'", branch)).into())
+}
+
+/// Ask the model to rate, from 0 to 10, how well `message` describes the diff.
+async fn score_message(args: &Args, message: &str) -> Result<u8> {
+    let (diff, path_tokens) = get_git_diff(args)?;
+
+    // The message has the real paths back in it by now
+    let message = redact_tokens(message, &path_tokens);
+
+    let messages = vec![
+        Message {
+            role: "system".to_string(),
+            content: "You review git commit messages. Rate how accurately and completely a commit message describes a diff on a scale from 0 (wrong or misleading) to 10 (precise and complete). Reply with only the number.".to_string(),
+        },
+        Message {
+            role: "user".to_string(),
+            content: format!("Commit message:\n{}\n\nDiff:\n{}", message, diff),
+        },
+    ];
+
+    // A deterministic rating is more useful than a creative one
+    let reply = send_chat_request(args, 0.0, messages).await?;
+
+    reply
+        .split(|c: char| !c.is_ascii_digit())
+        .find(|token| !token.is_empty())
+        .and_then(|token| token.parse::<u8>().ok())
+        .map(|score| score.min(10))
+        .ok_or_else(|| GaiError::Provider(format!("Could not read a score from the reply: {}", reply.trim())).into())
+}
+
+/// Ask the model for just a corrected scope or type for an existing message
+/// and splice it in, leaving the description and body untouched.
+async fn fix_message_prefix(args: &Args) -> Result<String> {
+    let message = match &args.message {
+        Some(message) => message.clone(),
+        None => std::io::read_to_string(std::io::stdin()).context(GaiError::Io("Failed to read the message from stdin".to_string()))?,
+    };
+    let message = message.trim();
+
+    let (header, body) = message.split_once('\n').unwrap_or((message, ""));
+    let subject = lint::parse_subject(header).ok_or_else(|| {
+        GaiError::Config(format!("\"{}\" is not a conventional commit subject line", header))
+    })?;
+
+    let (diff, path_tokens) = get_git_diff(args)?;
+    let redacted = redact_tokens(message, &path_tokens);
+
+    let part = if args.fix_scope { "scope" } else { "type" };
+    let instruction = if args.fix_scope {
+        "Reply with only the correct scope, or 'none' if the change has no single scope."
+    } else {
+        "Reply with only the correct type."
+    };
+
+    let messages = vec![
+        Message {
+            role: "system".to_string(),
+            content: load_system_prompt().to_string(),
+        },
+        Message {
+            role: "user".to_string(),
+            content: format!(
+                "This commit message was written for the diff below, but the {} in its subject line may be wrong:\n\n{}\n\n{}\n\n{}",
+                part, redacted, instruction, diff
+            ),
+        },
+    ];
+
+    // Only one word is wanted, so keep it deterministic
+    let reply = restore_tokens(&send_chat_request(args, 0.0, messages).await?, &path_tokens);
+    let answer = reply.trim().trim_matches(|c: char| matches!(c, '`' | '"' | '\'' | '(' | ')')).trim();
+
+    if answer.is_empty() || answer.contains(char::is_whitespace) {
+        return Err(GaiError::Provider(format!("Could not read a {} from the reply: {}", part, reply.trim())).into());
+    }
+
+    let (kind, scope) = match args.fix_scope {
+        true if answer.eq_ignore_ascii_case("none") => (subject.kind, None),
+        true => (subject.kind, Some(answer)),
+        false => (answer, subject.scope),
+    };
+
+    let mut fixed = kind.to_string();
+    if let Some(scope) = scope {
+        fixed.push_str(&format!("({})", scope));
+    }
+    if subject.breaking {
+        fixed.push('!');
+    }
+    fixed.push_str(&format!(": {}", subject.description));
+    if !body.is_empty() {
+        fixed.push_str(&format!("\n{}", body));
+    }
+
+    Ok(fixed)
+}
+
+/// Rules from the repository's commitlint config, if it has one, with
+/// `allowed_types` from the gai config taking precedence.
+fn message_rules(args: &Args) -> Result<Rules> {
+    let root = repository_root().unwrap_or_default();
+    let mut rules = lint::load_commitlint_rules(&root).unwrap_or_default();
+
+    if let Some(types) = &args.config.allowed_types {
+        rules.types = Some(types.clone());
+    }
+
+    Ok(rules)
+}
+
+/// Extra context about the change that the raw diff doesn't make obvious.
+fn prompt_hints(args: &Args, diff: &str, path_tokens: &[(String, String)]) -> Result<Vec<String>> {
+    let mut hints = Vec::new();
+
+    if args.since.is_none()
+        && let Some((sha, subject)) = detect_revert(diff)?
+    {
+        let subject = redact_tokens(&subject, path_tokens);
+        hints.push(format!(
+            "This change exactly undoes commit {} (\"{}\"); use the revert type, e.g. `revert: {}`.",
+            sha, subject, subject
+        ));
+    }
+
+    let files = if args.diff_command.is_some() { diff_paths(diff) } else { changed_files(args)? };
+
+    if !args.no_auto_scope
+        && !args.redact_paths
+        && let Some(scope) = detect_package_scope(&files)?
+    {
+        hints.push(format!("All changes are inside the `{}` package; use `{}` as the scope.", scope, scope));
+    }
+
+    // Models tend to call new tests a feat
+    let test_allowed = args.config.allowed_types.as_ref().is_none_or(|types| types.iter().any(|t| t == "test"));
+    if test_allowed && !files.is_empty() && files.iter().all(|file| is_test_file(file)) {
+        hints.push("Every changed file is a test file; use the `test` type.".to_string());
+    }
+
+    // Formatter runs read like real edits in a plain diff
+    let style_allowed = args.config.allowed_types.as_ref().is_none_or(|types| types.iter().any(|t| t == "style"));
+    if style_allowed
+        && args.config.whitespace_hint.unwrap_or(true)
+        && args.diff_command.is_none()
+        && changed_line_count(diff) > 0
+        && !has_visible_changes(&git_diff_report(args, "--ignore-all-space")?)
+    {
+        hints.push("Only whitespace and blank lines change; use the `style` type and say it's a whitespace-only change.".to_string());
+    }
+
+    // A branch name can say as much about the code as a path
+    if !args.no_branch_hint && !args.redact_paths && args.config.branch_hint.unwrap_or(true) {
+        let branch = git(&["rev-parse", "--abbrev-ref", "HEAD"]).unwrap_or_default();
+        let branch = branch.trim();
+        // Detached HEAD and the usual default branches say nothing about intent
+        if !matches!(branch, "" | "HEAD" | "main" | "master") {
+            hints.push(format!("The change was made on the branch `{}`; its name may say what the change is for.", branch));
+        }
+    }
+
+    if args.preset == Some(Preset::Jira)
+        && let Some(key) = jira_key_from_branch()
+    {
+        hints.push(format!("The Jira issue key for this change is {}.", key));
+    }
+
+    let language = match args.language.as_deref() {
+        Some("none") => None,
+        Some(language) => Some(language),
+        None => detect_language(&files),
+    };
+    if let Some(language) = language {
+        let hint = language_hint(language).ok_or_else(|| {
+            GaiError::Config(format!("Unknown language '{}'. Known languages: {}", language, LANGUAGES.join(", ")))
+        })?;
+        hints.push(hint.to_string());
+    }
+
+    Ok(hints)
+}
+
+/// Languages with a prompt hint, and the file extensions that identify them.
+const LANGUAGES: &[&str] = &["rust", "python", "javascript", "go", "java", "c"];
+
+fn language_for_extension(ext: &str) -> Option<&'static str> {
+    let language = match ext {
+        "rs" => "rust",
+        "py" | "pyi" => "python",
+        "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" => "javascript",
+        "go" => "go",
+        "java" | "kt" | "kts" => "java",
+        "c" | "h" | "cc" | "cpp" | "cxx" | "hpp" => "c",
+        _ => return None,
+    };
+
+    Some(language)
+}
+
+fn language_hint(language: &str) -> Option<&'static str> {
+    let hint = match language {
+        "rust" => "This is Rust code: pay attention to changes in public items, traits and `impl` blocks, derives, `unsafe` and Cargo features.",
+        "python" => "This is Python code: pay attention to changes in function signatures, decorators, type hints and imports.",
+        "javascript" => "This is JavaScript/TypeScript code: pay attention to changes in exports, types and interfaces, async behavior and dependencies.",
+        "go" => "This is Go code: pay attention to changes in exported identifiers, interfaces, error handling and goroutines.",
+        "java" => "This is JVM code: pay attention to changes in public classes and methods, interfaces and annotations.",
+        "c" => "This is C/C++ code: pay attention to changes in headers, public function signatures and memory management.",
+        _ => return None,
+    };
+
+    Some(hint)
+}
+
+/// The language of more than half of the changed files, if any.
+fn detect_language(files: &[String]) -> Option<&'static str> {
+    let languages = files
+        .iter()
+        .filter_map(|file| Path::new(file).extension()?.to_str())
+        .filter_map(language_for_extension)
+        .collect::<Vec<_>>();
+
+    LANGUAGES
+        .iter()
+        .copied()
+        .find(|language| languages.iter().filter(|l| *l == language).count() * 2 > files.len())
+}
+
+/// The --think step: a short summary of `diff` for the message prompt.
+async fn summarize_diff(args: &Args, temperature: f32, diff: &str) -> Result<String> {
+    let messages = vec![
+        Message {
+            role: "system".to_string(),
+            content: load_explain_prompt().to_string(),
+        },
+        Message {
+            role: "user".to_string(),
+            content: format!("Briefly summarize what this diff does and why, in at most five sentences:\n\n{}", diff),
+        },
+    ];
+
+    Ok(send_chat_request(args, temperature, messages).await?.trim().to_string())
+}
+
+async fn explain_diff(args: &Args) -> Result<String> {
+    let (diff, path_tokens) = get_git_diff(args)?;
+    let explanation = explain(args, &diff).await?;
+
+    Ok(restore_tokens(&explanation, &path_tokens))
+}
+
+async fn explain(args: &Args, diff: &str) -> Result<String> {
+    let temperature = resolve_temperature(args.temperature, args.temperature_auto, diff);
+
+    let messages = vec![
+        Message {
+            role: "system".to_string(),
+            content: load_explain_prompt().to_string(),
+        },
+        Message {
+            role: "user".to_string(),
+            content: format!("Explain what this diff does:\n\n{}", diff),
+        },
+    ];
+
+    let explanation = send_chat_request(args, temperature, messages).await?;
+
+    Ok(explanation.trim().to_string())
+}
+
+/// A commit message for a diff from outside the repository, such as a pull request.
+async fn remote_diff_message(args: &Args, diff: &str) -> Result<String> {
+    let temperature = resolve_temperature(args.temperature, args.temperature_auto, diff);
+
+    let messages = vec![
+        Message {
+            role: "system".to_string(),
+            content: preset_system_prompt(args)?,
+        },
+        Message {
+            role: "user".to_string(),
+            content: format!("Generate a single commit message that squashes all of these changes:\n\n{}", diff),
+        },
+    ];
+
+    let reply = send_chat_request(args, temperature, messages).await?;
+
+    Ok(clean_message(&reply))
+}
+
+async fn generate_pr_description(args: &Args) -> Result<String> {
+    let diff = get_branch_diff(args)?;
+
+    let temperature = resolve_temperature(args.temperature, args.temperature_auto, &diff);
+
+    let messages = vec![
+        Message {
+            role: "system".to_string(),
+            content: load_pr_prompt().to_string(),
+        },
+        Message {
+            role: "user".to_string(),
+            content: format!("Write a pull request title and description for this branch diff:\n\n{}", diff),
+        },
+    ];
+
+    let description = send_chat_request(args, temperature, messages).await?;
+
+    Ok(description.trim().to_string())
+}
+
+async fn generate_batch_messages(args: &Args) -> Result<Vec<String>> {
+    // Read every diff up front so a missing file fails before any API call
+    let mut diffs = Vec::with_capacity(args.batch.len());
+    for path in &args.batch {
+        let diff = fs::read_to_string(path)
+            .with_context(|| GaiError::Io(format!("Failed to read diff file {}", path.display())))?;
+
+        if diff.trim().is_empty() {
+            return Err(GaiError::Io(format!("Diff file {} is empty", path.display())).into());
+        }
+
+        diffs.push(diff);
+    }
+
+    let temperature = resolve_temperature(args.temperature, args.temperature_auto, &diffs.concat());
+
+    let mut content = format!(
+        "Generate one conventional commit message for each of the following {} diffs. \
+         Respond with only a JSON object of the form {{\"messages\": [\"...\"]}} containing exactly {} messages, in the same order as the diffs.",
+        diffs.len(),
+        diffs.len()
+    );
+    for (i, diff) in diffs.iter().enumerate() {
+        content.push_str(&format!("\n\n### Diff {}\n\n{}", i + 1, diff));
+    }
+
+    let messages = vec![
+        Message {
+            role: "system".to_string(),
+            content: preset_system_prompt(args)?,
+        },
+        Message {
+            role: "user".to_string(),
+            content,
+        },
+    ];
+
+    let reply = send_chat_request(args, temperature, messages).await?;
+
+    // Models sometimes wrap JSON in a markdown code fence
+    let json = reply
+        .trim()
+        .trim_start_matches("```json")
+        .trim_start_matches("```")
+        .trim_end_matches("```")
+        .trim();
+
+    let batch = serde_json::from_str::<BatchResponse>(json)
+        .context(GaiError::Provider("Failed to parse batch response as JSON".to_string()))?;
+
+    if batch.messages.len() != diffs.len() {
+        return Err(GaiError::Provider(format!(
+            "Expected {} messages in batch response, got {}",
+            diffs.len(),
+            batch.messages.len()
+        )).into());
+    }
+
+    Ok(batch.messages.iter().map(|m| clean_message(m)).collect())
+}
+
+/// Structured output format for --json-schema, with the type limited to
+/// `allowed_types` when configured.
+fn commit_message_schema(args: &Args) -> Value {
+    let kind = match &args.config.allowed_types {
+        Some(types) => json!({ "type": "string", "enum": types }),
+        None => json!({ "type": "string" }),
+    };
+
+    json!({
+        "type": "json_schema",
+        "json_schema": {
+            "name": "commit_message",
+            "strict": true,
+            "schema": {
+                "type": "object",
+                "properties": {
+                    "type": kind,
+                    "scope": { "type": ["string", "null"] },
+                    "description": { "type": "string" },
+                    "body": { "type": ["string", "null"] }
+                },
+                "required": ["type", "scope", "description", "body"],
+                "additionalProperties": false
+            }
+        }
+    })
+}
+
+async fn send_chat_request(args: &Args, temperature: f32, messages: Vec<Message>) -> Result<String> {
+    send_chat_request_with_format(args, temperature, messages, None).await
+}
+
+/// Like `send_chat_request`, optionally asking for a structured reply.
+async fn send_chat_request_with_format(
+    args: &Args,
+    temperature: f32,
+    messages: Vec<Message>,
+    response_format: Option<Value>,
+) -> Result<String> {
+    let messages = if supports_system_role(args) { messages } else { merge_system_messages(messages) };
+
+    // Debugging aid: dump exactly what would be sent and stop before any network access
+    if args.show_prompt {
+        println!("provider: {:?}", args.provider);
+        println!("model: {}", args.model);
+        println!("temperature: {}", temperature);
+        if let Some(format) = &response_format {
+            println!("response_format: {}", format);
+        }
+        for message in &messages {
+            println!("\n--- {} ---\n{}", message.role, message.content);
+        }
+        return Err(PromptShown.into());
+    }
+
+    // A reply cached by --prewarm for exactly this request makes the call unnecessary
+    let cache_key = cache::key(
+        &json!({
+            "provider": format!("{:?}", args.provider),
+            "base_url": args.base_url,
+            "api": format!("{:?}", args.api),
+            "model": args.model,
+            "temperature": temperature,
+            "messages": messages,
+            "response_format": response_format,
+            "extra_params": args.extra_params,
+        })
+        .to_string(),
+    );
+    if !args.prewarm
+        && let Some(reply) = cache::take(&cache_key)
+    {
+        return Ok(clean_reply(args, reply));
+    }
+
+    let input_tokens = messages.iter().map(|m| cost::estimate_tokens(&m.content)).sum();
+
+    if let Some(max_cost) = args.max_cost {
+        let estimate = cost::cost(&args.model, input_tokens, cost::ESTIMATED_OUTPUT_TOKENS).ok_or_else(|| {
+            GaiError::Config(format!("No price is known for model '{}', so --max-cost can't be enforced", args.model))
+        })?;
+
+        if cost::spent() + estimate > max_cost {
+            return Err(GaiError::Config(format!(
+                "Estimated cost ${:.4} (plus ${:.4} already spent) exceeds --max-cost ${:.4}. Stage fewer changes or raise the limit.",
+                estimate,
+                cost::spent(),
+                max_cost
+            )).into());
+        }
+    }
+
+    if args.api == Api::Responses && args.provider != Provider::OpenAI {
+        return Err(GaiError::Config(format!("--api responses is not supported by the {:?} provider", args.provider)).into());
+    }
+
+    if response_format.is_some() && args.provider != Provider::OpenAI {
+        return Err(GaiError::Config(format!("--json-schema is not supported by the {:?} provider", args.provider)).into());
+    }
+
+    let _slot = REQUEST_SLOTS
+        .get_or_init(|| Semaphore::new(DEFAULT_CONCURRENCY))
+        .acquire()
+        .await
+        .expect("the request semaphore is never closed");
+
+    let params = Params { temperature, response_format };
+    let completion = match args.provider {
+        Provider::OpenAI => OPENAI.complete(args, messages, params).await?,
+        Provider::DeepSeek => DEEPSEEK.complete(args, messages, params).await?,
+        Provider::Mistral => MISTRAL.complete(args, messages, params).await?,
+        Provider::HuggingFace => HuggingFace.complete(args, messages, params).await?,
+        Provider::Vertex => Vertex.complete(args, messages, params).await?,
+        Provider::Ollama => Ollama.complete(args, messages, params).await?,
+    };
+
+    // Prefer the provider's own token counts over our estimate
+    let (input_tokens, output_tokens) = match &completion.usage {
+        Some(usage) => (usage.prompt_tokens, usage.completion_tokens),
+        None => (input_tokens, cost::estimate_tokens(&completion.text)),
+    };
+    if let Some(amount) = cost::cost(&args.model, input_tokens, output_tokens) {
+        cost::record(amount);
+    }
+
+    if args.prewarm {
+        cache::store(&cache_key, &completion.text)?;
+    }
+
+    Ok(clean_reply(args, completion.text))
+}
+
+/// Remove whatever the configured `cleanup_regex` matches, e.g. a
+/// disclaimer a model appends to every reply.
+fn clean_reply(args: &Args, reply: String) -> String {
+    match &args.config.cleanup_regex {
+        Some(regex) => regex.replace_all(&reply, "").into_owned(),
+        None => reply,
+    }
+}
+
+/// Whether the endpoint accepts `system` messages. Some older reasoning
+/// models don't; config and --no-system-role cover any others.
+fn supports_system_role(args: &Args) -> bool {
+    if args.no_system_role {
+        return false;
+    }
+
+    args.config
+        .system_role
+        .unwrap_or(!matches!(args.model.as_str(), "o1-mini" | "o1-preview"))
+}
+
+/// Prepend the system prompt to the first user message instead of sending it
+/// as its own message.
+fn merge_system_messages(messages: Vec<Message>) -> Vec<Message> {
+    let (system, mut rest): (Vec<_>, Vec<_>) = messages.into_iter().partition(|m| m.role == "system");
+    if system.is_empty() {
+        return rest;
+    }
+
+    let system = system.into_iter().map(|m| m.content).collect::<Vec<_>>().join("\n\n");
+    match rest.iter_mut().find(|m| m.role == "user") {
+        Some(user) => user.content = format!("{}\n\n{}", system, user.content),
+        None => rest.insert(0, Message {
+            role: "user".to_string(),
+            content: system,
+        }),
+    }
+
+    rest
+}
+
+/// Fail fast, with suggestions, when the provider doesn't list the model.
+async fn verify_model(args: &Args) -> Result<()> {
+    let endpoint = args.provider.openai_compatible().ok_or_else(|| {
+        GaiError::Config(format!("--verify-model is not supported by the {:?} provider", args.provider))
+    })?;
+    let api_keys = providers::read_api_keys(endpoint.api_key_var)?;
+
+    endpoint.check_model_listed(args, &api_keys[0]).await
+}
+
+/// Levenshtein distance between two strings, by characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+fn http_client() -> Result<Client> {
+    // Without a connect timeout a blocked network hangs until the OS gives up
+    Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .build()
+        .context(GaiError::Provider("Failed to create HTTP client".to_string()))
+}
+
+/// Tell network policy failures apart from genuine API errors.
+fn request_error(err: reqwest::Error, service: &str) -> anyhow::Error {
+    if err.is_connect() {
+        anyhow::Error::new(err).context(GaiError::Provider(format!(
+            "Cannot reach the {}; is this a no-network environment?",
+            service
+        )))
+    } else if err.is_timeout() {
+        anyhow::Error::new(err).context(GaiError::Provider(format!("The {} didn't answer in time", service)))
+    } else {
+        anyhow::Error::new(err).context(GaiError::Provider(format!("Failed to send request to {}", service)))
+    }
+}
+
+fn parse_header(raw: &str) -> Result<(String, String), String> {
+    let (name, value) = raw
+        .split_once(':')
+        .ok_or_else(|| format!("expected \"Key: Value\", got \"{}\"", raw))?;
+    let (name, value) = (name.trim(), value.trim());
+
+    HeaderName::from_bytes(name.as_bytes()).map_err(|_| format!("invalid header name \"{}\"", name))?;
+    HeaderValue::from_str(value).map_err(|_| format!("invalid value for header \"{}\"", name))?;
+
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// Request fields gitai fills in itself, which an extra parameter would duplicate.
+const RESERVED_PARAMS: &[&str] = &[
+    "model", "messages", "temperature", "response_format", "stream",
+    "inputs", "parameters", "max_new_tokens", "return_full_text",
+];
+
+fn parse_type_verb(raw: &str) -> Result<(String, String), String> {
+    let (kind, verb) = raw
+        .split_once('=')
+        .ok_or_else(|| format!("expected \"type=verb\", got \"{}\"", raw))?;
+    let (kind, verb) = (kind.trim(), verb.trim());
+
+    if kind.is_empty() || verb.contains(char::is_whitespace) {
+        return Err(format!("expected \"type=verb\" with a one-word verb, got \"{}\"", raw));
+    }
+
+    Ok((kind.to_string(), verb.to_string()))
+}
+
+fn parse_extra_param(raw: &str) -> Result<(String, Value), String> {
+    let (key, value) = raw
+        .split_once('=')
+        .ok_or_else(|| format!("expected \"key=value\", got \"{}\"", raw))?;
+    let (key, value) = (key.trim(), value.trim());
+
+    if key.is_empty() {
+        return Err(format!("missing parameter name in \"{}\"", raw));
+    }
+    if RESERVED_PARAMS.contains(&key) {
+        return Err(format!("\"{}\" is set by gitai itself; use its own flag instead", key));
+    }
+
+    let value = match value {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        "null" => Value::Null,
+        _ if value.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') => {
+            // Something that looks like a number but isn't one is almost always a typo
+            let invalid = || format!("invalid number \"{}\" for \"{}\"; quote it to send a string", value, key);
+            match value.parse::<i64>() {
+                Ok(integer) => Value::from(integer),
+                Err(_) => {
+                    let float = value.parse::<f64>().map_err(|_| invalid())?;
+                    serde_json::Number::from_f64(float).map(Value::Number).ok_or_else(invalid)?
+                }
+            }
+        }
+        // Quotes force a string, e.g. stop="42"
+        _ => Value::String(value.trim_matches('"').to_string()),
+    };
+
+    Ok((key.to_string(), value))
+}
+
+fn resolve_temperature(temperature: Option<f32>, temperature_auto: bool, diff: &str) -> f32 {
+    match temperature {
+        Some(temperature) => temperature,
+        None if temperature_auto => auto_temperature(diff),
+        None => DEFAULT_TEMPERATURE,
+    }
+}
+
+/// Large diffs carry enough signal for a crisp, deterministic message, while
+/// tiny ones benefit from a little more creativity.
+fn auto_temperature(diff: &str) -> f32 {
+    let changed_lines = diff
+        .lines()
+        .filter(|line| {
+            (line.starts_with('+') && !line.starts_with("+++"))
+                || (line.starts_with('-') && !line.starts_with("---"))
+        })
+        .count();
+
+    match changed_lines {
+        0..=10 => 1.0,
+        11..=50 => 0.8,
+        51..=200 => 0.6,
+        _ => 0.4,
+    }
+}
+
+fn clean_message(message: &str) -> String {
+    // Clean up the message (remove quotes if present, trim whitespace)
+    let message = message
+        .trim()
+        .trim_matches('"')
+        .trim();
+
+    format_subject_and_body(message)
+}
+
+/// Make sure the subject is a single line separated from any body by a
+/// blank line, so `git commit -m` never records a wrapped subject.
+fn format_subject_and_body(message: &str) -> String {
+    let message = message.replace("\r\n", "\n");
+    let mut lines = message.lines().skip_while(|line| line.trim().is_empty());
+
+    let subject = lines.next().unwrap_or_default().trim().to_string();
+    let body = lines
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string();
+
+    if body.is_empty() {
+        subject
+    } else {
+        format!("{}\n\n{}", subject, body)
+    }
+}
+
+/// The message for an --allow-empty commit: --message, else ask, else a
+/// generic one.
+fn empty_commit_message(args: &Args) -> Result<String> {
+    if let Some(message) = &args.message {
+        return Ok(message.clone());
+    }
+    if !std::io::stdin().is_terminal() {
+        return Ok(EMPTY_COMMIT_MESSAGE.to_string());
+    }
+
+    Input::new()
+        .with_prompt("Message for the empty commit")
+        .default(EMPTY_COMMIT_MESSAGE.to_string())
+        .interact_text()
+        .context(GaiError::Io("Failed to read the commit message".to_string()))
+}
+
+/// Added plus removed lines in a unified diff.
+fn changed_line_count(diff: &str) -> usize {
+    diff.lines()
+        .filter(|line| (line.starts_with('+') && !line.starts_with("+++")) || (line.starts_with('-') && !line.starts_with("---")))
+        .count()
+}
+
+/// Whether `diff` adds or removes anything but blank lines.
+fn has_visible_changes(diff: &str) -> bool {
+    diff.lines()
+        .filter(|line| !line.starts_with("+++") && !line.starts_with("---"))
+        .any(|line| (line.starts_with('+') || line.starts_with('-')) && !line[1..].trim().is_empty())
+}
+
+/// Cut the message off before its bullet point number `max + 1`.
+fn limit_bullets(message: &str, max: usize) -> String {
+    let mut bullets = 0;
+    let mut kept = Vec::new();
+
+    for line in message.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("- ") || trimmed.starts_with("* ") {
+            bullets += 1;
+        }
+        if bullets > max {
+            break;
+        }
+        kept.push(line);
+    }
+
+    kept.join("\n").trim_end().to_string()
+}
+
+fn get_git_diff(args: &Args) -> Result<(String, Vec<(String, String)>)> {
+    let diff = match &args.diff_command {
+        Some(command) => command_diff(args, command)?,
+        None => {
+            ensure_git_repository()?;
+            check_do_not_send(args, &diff_revisions(args), true)?;
+            let mut diff = run_git_diff(args, &diff_revisions(args))?;
+            if diffs_index(args) {
+                // `git add -N` files are empty in the index, so their content is only in the working tree
+                check_do_not_send(args, &[INTENT_TO_ADD_FILTER], true)?;
+                diff.push_str(&run_git_diff(args, &[INTENT_TO_ADD_FILTER])?);
+            }
+            diff
+        }
+    };
+
+    if diff.is_empty() && !args.allow_empty {
+        if let Some(command) = &args.diff_command {
+            return Err(GaiError::Git(format!("The diff command printed nothing: {}", command)).into());
+        }
+        if !args.include_ext.is_empty() {
+            return Err(GaiError::Git(format!("No changes found in files with extensions: {}", args.include_ext.join(", "))).into());
+        }
+        if let Some(since) = &args.since {
+            return Err(GaiError::Git(format!("No changes found since {}.", since)).into());
+        }
+        if let Some(stash) = &args.stash {
+            return Err(GaiError::Git(format!("No changes found in {}.", stash)).into());
+        }
+        // The usual mistake is forgetting to stage, so say so when that's the case
+        let status = git(&["status", "--porcelain"]).unwrap_or_default();
+        if !status.trim().is_empty() {
+            return Err(GaiError::Git(format!(
+                "No staged changes found, but you have unstaged or untracked changes:\n{}\nRun 'git add' to stage them.",
+                status.trim_end()
+            )).into());
+        }
+        return Err(GaiError::Git("No staged changes found. Use 'git add' to stage your changes.".to_string()).into());
+    }
+
+    // A resolved-looking commit with markers in it breaks the build for everyone
+    let conflicted = conflicted_files(&diff);
+    if !conflicted.is_empty() && !args.force {
+        return Err(GaiError::Git(format!(
+            "The changes contain merge conflict markers in: {}. Resolve them, or pass --force if they're intended.",
+            conflicted.join(", ")
+        )).into());
+    }
+
+    // `// gai: ...` comments are notes for the model, not part of the change
+    let (diff, notes) = extract_annotations(&diff);
+    if !notes.is_empty() && args.commit {
+        eprintln!("⚠️ The staged changes still contain {} gai: note(s); remove them once you're done", notes.len());
+    }
+    let diff = if notes.is_empty() {
+        diff
+    } else {
+        let notes = notes.iter().map(|note| format!("- {}", note)).collect::<Vec<_>>().join("\n");
+        format!("Notes from the author about this change:\n{}\n\n{}", notes, diff)
+    };
+
+    // Usually a build artifact or a dump that was staged by accident
+    let large = if args.stash.is_some() || args.diff_command.is_some() { Vec::new() } else { large_added_files(args)? };
+    if !large.is_empty() && !args.force {
+        let list = large
+            .iter()
+            .map(|(file, size)| format!("{} ({} KiB)", file, size / 1024))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if args.commit || args.interactive || args.auto_split || args.split_by_package {
+            return Err(GaiError::Git(format!(
+                "Refusing to commit large new files: {}. Unstage them, or pass --force if they belong in the repository.",
+                list
+            )).into());
+        }
+        eprintln!("⚠️ Large new files are staged: {}", list);
+    }
+
+    let diff = if args.compact_diff { compact_diff(&diff) } else { diff };
+    let diff = match args.diff_file_limit {
+        Some(limit) => limit_file_diffs(&diff, limit),
+        None => diff,
+    };
+
+    // The file list keeps every file in view however much of its diff is cut
+    let files = || match args.diff_command.is_some() || args.redact_paths {
+        true => Ok(String::new()),
+        false => git_diff_report(args, "--numstat").map(|numstat| file_list(&numstat)),
+    };
+    let mut diff = fit_diff(args, diff, files)?;
+
+    // An overview of where the changes are helps scope a sprawling commit
+    if !args.redact_paths
+        && args.diff_command.is_none()
+        && let Some(summary) = directory_summary(&git_diff_report(args, "--numstat")?)
+    {
+        diff = format!("{}\n{}", summary, diff);
+    }
+
+    // Renames and mode changes barely show up in the diff itself
+    if args.include_summary && args.diff_command.is_none() {
+        let summary = git_diff_report(args, "--summary")?;
+        if !summary.trim().is_empty() {
+            diff.push_str(&format!("\nSummary of file changes:\n{}", summary));
+        }
+    }
+
+    if args.redact_paths {
+        return Ok(redact_paths(&diff));
+    }
+
+    Ok((diff, Vec::new()))
+}
+
+/// The output of --diff-command / `diff_command`. There's no file list to
+/// check `do_not_send` against, so the two can't be combined.
+fn command_diff(args: &Args, command: &str) -> Result<String> {
+    if !do_not_send_pathspecs(args, "").is_empty() {
+        return Err(GaiError::Config("do_not_send can't be enforced on the output of diff_command; remove one of them".to_string()).into());
+    }
+
+    let diff = run_shell(command, "")?;
+    Ok(if diff.trim().is_empty() { String::new() } else { diff })
+}
+
+/// Paths in the `diff --git` headers of `diff`, new names for renames.
+fn diff_paths(diff: &str) -> Vec<String> {
+    diff.lines()
+        .filter_map(|line| line.strip_prefix("diff --git "))
+        .map(|rest| split_diff_git_paths(rest).1.to_string())
+        .collect()
+}
+
+/// Comment markers a `gai:` annotation may follow.
+const COMMENT_MARKERS: &[&str] = &["//", "#", "--", "/*", "<!--", ";"];
+
+/// Pull `gai: <note>` comments out of the added lines of `diff`, e.g.
+/// `// gai: this fixes issue #42`. Returns the diff without them and the notes.
+fn extract_annotations(diff: &str) -> (String, Vec<String>) {
+    let mut stripped = String::with_capacity(diff.len());
+    let mut notes = Vec::new();
+
+    for line in diff.split_inclusive('\n') {
+        let note = line
+            .strip_prefix('+')
+            .filter(|_| !line.starts_with("+++ "))
+            .map(str::trim)
+            .and_then(|code| COMMENT_MARKERS.iter().find_map(|marker| code.strip_prefix(marker)))
+            .and_then(|comment| comment.trim_start().strip_prefix("gai:"))
+            .map(|note| note.trim().trim_end_matches("*/").trim_end_matches("-->").trim());
+
+        match note {
+            Some(note) if !note.is_empty() => notes.push(note.to_string()),
+            _ => stripped.push_str(line),
+        }
+    }
+
+    (stripped, notes)
+}
+
+/// Staged new files over the `large_file_kb` limit, with their sizes in bytes.
+fn large_added_files(args: &Args) -> Result<Vec<(String, u64)>> {
+    let limit = args.config.large_file_kb.unwrap_or(DEFAULT_LARGE_FILE_KB) * 1024;
+
+    let mut command = vec!["diff", "--cached", "--diff-filter=A", "--name-only", "-z", "--"];
+    let pathspecs = pathspecs(args);
+    command.extend(pathspecs.iter().map(String::as_str));
+
+    let mut large = Vec::new();
+    for file in git(&command)?.split('\0').filter(|file| !file.is_empty()) {
+        let size = git(&["cat-file", "-s", &format!(":{}", file)])?.trim().parse().unwrap_or(0);
+        if size > limit {
+            large.push((file.to_string(), size));
+        }
+    }
+
+    Ok(large)
+}
+
+/// Files whose added lines include `<<<<<<<` or `>>>>>>>` conflict markers.
+/// `=======` alone is left out, since it also underlines reStructuredText headings.
+fn conflicted_files(diff: &str) -> Vec<String> {
+    let mut files: Vec<String> = Vec::new();
+    let mut current = "";
+    for line in diff.lines() {
+        if let Some(rest) = line.strip_prefix("diff --git ") {
+            current = split_diff_git_paths(rest).1;
+        } else if (line.starts_with("+<<<<<<<") || line.starts_with("+>>>>>>>"))
+            && !files.iter().any(|file| file == current)
+        {
+            files.push(current.to_string());
+        }
+    }
+
+    files
+}
+
+/// Group `git diff --numstat` output by top-level directory, largest first.
+/// Returns None when everything is in one place, where it adds nothing.
+fn directory_summary(numstat: &str) -> Option<String> {
+    let mut groups: Vec<(String, usize, usize, usize)> = Vec::new();
+    for line in numstat.lines() {
+        let mut fields = line.splitn(3, '\t');
+        let (Some(added), Some(deleted), Some(path)) = (fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        // Binary files are listed with "-" counts
+        let added = added.parse().unwrap_or(0);
+        let deleted = deleted.parse().unwrap_or(0);
+        let directory = match path.split_once('/') {
+            Some((directory, _)) => format!("{}/", directory),
+            None => "(root)".to_string(),
+        };
+
+        match groups.iter_mut().find(|(name, ..)| *name == directory) {
+            Some(group) => {
+                group.1 += 1;
+                group.2 += added;
+                group.3 += deleted;
+            }
+            None => groups.push((directory, 1, added, deleted)),
+        }
+    }
+
+    if groups.len() < 2 {
+        return None;
+    }
+
+    groups.sort_by_key(|(_, _, added, deleted)| std::cmp::Reverse(added + deleted));
+    let mut summary = "Changed directories:\n".to_string();
+    for (directory, files, added, deleted) in groups {
+        summary.push_str(&format!(
+            "{} {} file{}, +{}/-{}\n",
+            directory,
+            files,
+            if files == 1 { "" } else { "s" },
+            added,
+            deleted
+        ));
+    }
+
+    Some(summary)
+}
+
+/// Condense `diff` when it's over max_diff_bytes, listing `files` (the
+/// changed files, if known) at the top.
+fn fit_diff(args: &Args, diff: String, files: impl FnOnce() -> Result<String>) -> Result<String> {
+    let Some(max_bytes) = max_diff_bytes(args)? else {
+        return Ok(diff);
+    };
+    if diff.len() <= max_bytes {
+        return Ok(diff);
+    }
+
+    let known_window = args.config.context_windows.as_ref().is_some_and(|windows| windows.contains_key(&args.model))
+        || cost::context_window(&args.model).is_some();
+    let limit = if args.max_diff_bytes.is_some() {
+        "--max-diff-bytes".to_string()
+    } else if known_window {
+        format!("{}'s context window", args.model)
+    } else {
+        "a model with an unknown context window".to_string()
+    };
+
+    let condensed = condense_diff(&diff, &files()?, max_bytes).ok_or_else(|| {
+        GaiError::Config(format!(
+            "None of the diff fits in {} bytes, the limit for {}; raise --max-diff-bytes or stage fewer changes",
+            max_bytes, limit
+        ))
+    })?;
+
+    eprintln!(
+        "⚠️ Diff truncated to fit context: it's over {} bytes, the limit for {}, so the model sees the changed files and the start of each file's diff, and the message may miss details.",
+        max_bytes, limit
+    );
+    Ok(condensed)
+}
+
+/// Room left for prompt hints and framing around the diff when sizing it
+/// to the context window.
+const PROMPT_SLACK_TOKENS: usize = 512;
+
+/// Most bytes of diff to send to a model whose context window isn't known.
+/// Keep the --max-diff-bytes doc in step with it.
+const DEFAULT_MAX_DIFF_BYTES: usize = 100_000;
+
+/// --max-diff-bytes (no limit when it's 0), or what's left of the model's
+/// context window (from the `context_windows` config or the built-in table)
+/// after the system prompt and the reply.
+fn max_diff_bytes(args: &Args) -> Result<Option<usize>> {
+    if let Some(max_bytes) = args.max_diff_bytes {
+        return Ok(Some(max_bytes).filter(|&max_bytes| max_bytes > 0));
+    }
+
+    let configured = args.config.context_windows.as_ref().and_then(|windows| windows.get(&args.model).copied());
+    let Some(window) = configured.or_else(|| cost::context_window(&args.model)) else {
+        return Ok(Some(DEFAULT_MAX_DIFF_BYTES));
+    };
+
+    let overhead = cost::estimate_tokens(&preset_system_prompt(args)?) + cost::ESTIMATED_OUTPUT_TOKENS + PROMPT_SLACK_TOKENS;
+    Ok(Some(window.saturating_sub(overhead) * cost::CHARS_PER_TOKEN))
+}
+
+/// Fit a diff over `max_bytes` into it: `files`, a list of the changed
+/// files, then every file's diff cut to the same number of lines, as many
+/// as fit. This way one huge file can't crowd the others out, and binary
+/// files keep their one-line summary. When even the file headers are too
+/// much, only the start of them is kept; `None` when not even that fits.
+fn condense_diff(diff: &str, files: &str, max_bytes: usize) -> Option<String> {
+    let files = if files.is_empty() { String::new() } else { format!("Changed files:\n{}\n", files) };
+    // Without room for the list, the headers in the diff have to do
+    let files = if files.len() + limit_file_diffs(diff, 0).len() <= max_bytes { files } else { String::new() };
+    let with_limit = |limit| format!("{}{}", files, limit_file_diffs(diff, limit));
+
+    let limit = largest_fitting_limit(diff.lines().count(), |limit| with_limit(limit).len() <= max_bytes);
+    let condensed = with_limit(limit);
+    match truncate_diff(&condensed, max_bytes) {
+        None => Some(condensed),
+        Some(truncated) if truncated.kept.is_empty() => None,
+        Some(truncated) => Some(truncated.to_string()),
+    }
+}
+
+/// The largest line limit up to `most` that `fits`, or 0.
+fn largest_fitting_limit(most: usize, fits: impl Fn(usize) -> bool) -> usize {
+    let (mut low, mut high) = (0, most);
+    while low < high {
+        let middle = (low + high).div_ceil(2);
+        if fits(middle) {
+            low = middle;
+        } else {
+            high = middle - 1;
+        }
+    }
+    low
+}
+
+/// `git diff --numstat` as one `path (+added -removed)` line per file.
+fn file_list(numstat: &str) -> String {
+    numstat
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            let (added, removed, path) = (fields.next()?, fields.next()?, fields.next()?);
+            Some(match added {
+                "-" => format!("{} (binary)\n", path),
+                _ => format!("{} (+{} -{})\n", path, added, removed),
+            })
+        })
+        .collect()
+}
+
+/// The start of a diff that was cut to size, and how many lines were left out.
+/// Displays as the kept lines followed by a note of what was dropped.
+#[derive(Debug, PartialEq, Eq)]
+struct Truncated<'a> {
+    kept: &'a str,
+    dropped_lines: usize,
+}
+
+impl std::fmt::Display for Truncated<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}[truncated: {} more lines of the diff]", self.kept, self.dropped_lines)
+    }
+}
+
+/// Cut `diff` after the last whole line that fits in `max_bytes`, or `None`
+/// when it already fits.
+fn truncate_diff(diff: &str, max_bytes: usize) -> Option<Truncated<'_>> {
+    if diff.len() <= max_bytes {
+        return None;
+    }
+
+    let mut end = 0;
+    for line in diff.split_inclusive('\n') {
+        if end + line.len() > max_bytes {
+            break;
+        }
+        end += line.len();
+    }
+
+    Some(Truncated {
+        kept: &diff[..end],
+        dropped_lines: diff[end..].lines().count(),
+    })
+}
+
+/// Replace each run of two or more unchanged context lines inside a hunk
+/// with a single marker, keeping headers and every +/- line.
+fn compact_diff(diff: &str) -> String {
+    let mut compact = String::with_capacity(diff.len());
+    let mut context = Vec::new();
+    let mut in_hunks = false;
+
+    let flush = |compact: &mut String, context: &mut Vec<&str>| {
+        match context.len() {
+            0 => {}
+            1 => compact.push_str(context[0]),
+            n => compact.push_str(&format!(" [{} unchanged lines]\n", n)),
+        }
+        context.clear();
+    };
+
+    for line in diff.split_inclusive('\n') {
+        if line.starts_with("diff --git ") {
+            in_hunks = false;
+        } else if line.starts_with("@@") {
+            in_hunks = true;
+        }
+
+        if in_hunks && line.starts_with(' ') {
+            context.push(line);
+        } else {
+            flush(&mut compact, &mut context);
+            compact.push_str(line);
+        }
+    }
+    flush(&mut compact, &mut context);
+
+    compact
+}
+
+/// Keep every file's header but only the first `limit` lines of its hunks,
+/// noting how much of each file was cut.
+fn limit_file_diffs(diff: &str, limit: usize) -> String {
+    let mut limited = String::with_capacity(diff.len());
+    let mut kept = 0;
+    let mut dropped = 0;
+    let mut in_hunks = false;
+
+    let note = |limited: &mut String, dropped: usize| {
+        if dropped > 0 {
+            limited.push_str(&format!("[truncated: {} more lines of this file]\n", dropped));
+        }
+    };
+
+    for line in diff.split_inclusive('\n') {
+        if line.starts_with("diff --git ") {
+            note(&mut limited, dropped);
+            (kept, dropped, in_hunks) = (0, 0, false);
+        } else if line.starts_with("@@") {
+            in_hunks = true;
+        }
+
+        if !in_hunks {
+            limited.push_str(line);
+        } else if kept < limit {
+            limited.push_str(line);
+            kept += 1;
+        } else {
+            dropped += 1;
+        }
+    }
+    note(&mut limited, dropped);
+
+    limited
+}
+
+/// Swap every path in the file headers of `diff` for a token that keeps
+/// only the extension, e.g. `file1.rs`. Returns the rewritten diff and the
+/// (path, token) pairs in order of first appearance.
+fn redact_paths(diff: &str) -> (String, Vec<(String, String)>) {
+    let mut tokens: Vec<(String, String)> = Vec::new();
+    let mut token_for = |path: &str| {
+        if let Some((_, token)) = tokens.iter().find(|(known, _)| known == path) {
+            return token.clone();
+        }
+        let mut token = format!("file{}", tokens.len() + 1);
+        if let Some(extension) = Path::new(path).extension() {
+            token.push_str(&format!(".{}", extension.to_string_lossy()));
+        }
+        tokens.push((path.to_string(), token.clone()));
+        token
+    };
+
+    let mut redacted = String::with_capacity(diff.len());
+    let mut in_header = false;
+    for line in diff.split_inclusive('\n') {
+        let (text, newline) = match line.strip_suffix('\n') {
+            Some(text) => (text, "\n"),
+            None => (line, ""),
+        };
+
+        if let Some(rest) = text.strip_prefix("diff --git ") {
+            in_header = true;
+            let (old, new) = split_diff_git_paths(rest);
+            let (old, new) = (token_for(old), token_for(new));
+            redacted.push_str(&format!("diff --git a/{} b/{}{}", old, new, newline));
+            continue;
+        }
+        if text.starts_with("@@") {
+            in_header = false;
+        }
+        if !in_header {
+            redacted.push_str(line);
+            continue;
+        }
+
+        let prefixes = ["--- a/", "+++ b/", "rename from ", "rename to ", "copy from ", "copy to "];
+        if let Some(prefix) = prefixes.iter().find(|prefix| text.starts_with(*prefix)) {
+            let token = token_for(&text[prefix.len()..]);
+            redacted.push_str(&format!("{}{}{}", prefix, token, newline));
+        } else if let Some(rest) = text.strip_prefix("Binary files ") {
+            // "Binary files a/x and b/y differ", either side may be /dev/null
+            let rest = rest.strip_suffix(" differ").unwrap_or(rest);
+            let redact = |side: &str, prefix: &str, token_for: &mut dyn FnMut(&str) -> String| match side.strip_prefix(prefix) {
+                Some(path) => format!("{}{}", prefix, token_for(path)),
+                None => side.to_string(),
+            };
+            let (old, new) = rest.split_once(" and ").unwrap_or((rest, ""));
+            redacted.push_str(&format!(
+                "Binary files {} and {} differ{}",
+                redact(old, "a/", &mut token_for),
+                redact(new, "b/", &mut token_for),
+                newline
+            ));
+        } else {
+            redacted.push_str(line);
+        }
+    }
+
+    (redacted, tokens)
+}
+
+/// Split the `a/<old> b/<new>` part of a `diff --git` line. Paths may hold
+/// spaces, so prefer the split that makes both halves equal.
+fn split_diff_git_paths(rest: &str) -> (&str, &str) {
+    let middle = rest.len() / 2;
+    if rest.is_char_boundary(middle)
+        && let (Some(old), Some(new)) = (rest[..middle].strip_prefix("a/"), rest[middle..].strip_prefix(" b/"))
+        && old == new
+    {
+        return (old, new);
+    }
+
+    match rest.split_once(" b/") {
+        Some((old, new)) => (old.strip_prefix("a/").unwrap_or(old), new),
+        None => (rest, rest),
+    }
+}
+
+/// Swap the paths in `text` for their --redact-paths tokens, longest first
+/// so a path isn't half replaced by one it starts with.
+fn redact_tokens(text: &str, tokens: &[(String, String)]) -> String {
+    let mut tokens = tokens.iter().collect::<Vec<_>>();
+    tokens.sort_by_key(|(path, _)| std::cmp::Reverse(path.len()));
+
+    tokens.iter().fold(text.to_string(), |text, (path, token)| text.replace(path.as_str(), token))
+}
+
+/// Put the real paths back where the model used a --redact-paths token,
+/// either whole (`file1.rs`) or as a bare stem, as in a scope (`file1`).
+fn restore_tokens(text: &str, tokens: &[(String, String)]) -> String {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut restored = String::with_capacity(text.len());
+    let mut rest = text;
+
+    // One pass, so a restored path can never be mistaken for another token
+    while let Some(start) = rest.find("file") {
+        let before = rest[..start].chars().next_back();
+        let digits = rest[start + 4..].chars().take_while(char::is_ascii_digit).count();
+        let number = rest[start + 4..start + 4 + digits].parse::<usize>().ok();
+
+        let found = number
+            .filter(|_| !before.is_some_and(is_word))
+            .and_then(|number| tokens.get(number.wrapping_sub(1)))
+            .and_then(|(path, token)| {
+                let after = &rest[start..];
+                let whole = after.starts_with(token.as_str()) && !after[token.len()..].starts_with(is_word);
+                let stem = 4 + digits;
+                if whole {
+                    Some((token.len(), path.clone()))
+                } else if !after[stem..].starts_with(is_word) {
+                    let real_stem = Path::new(path).file_stem().map_or_else(|| path.clone(), |stem| stem.to_string_lossy().to_string());
+                    Some((stem, real_stem))
+                } else {
+                    None
+                }
+            });
+
+        match found {
+            Some((length, replacement)) => {
+                restored.push_str(&rest[..start]);
+                restored.push_str(&replacement);
+                rest = &rest[start + length..];
+            }
+            None => {
+                restored.push_str(&rest[..start + 4]);
+                rest = &rest[start + 4..];
+            }
+        }
+    }
+    restored.push_str(rest);
+
+    restored
+}
+
+/// Diff the working tree against --since, a stash against its base commit
+/// (what `git stash show -p` prints), or the index against HEAD.
+fn diff_revisions(args: &Args) -> Vec<String> {
+    match (&args.since, &args.stash) {
+        (Some(since), _) => vec![since.clone()],
+        (None, Some(stash)) => vec![format!("{}^1", stash), stash.clone()],
+        (None, None) => std::iter::once("--staged".to_string()).chain(args.staged_base.clone()).collect(),
+    }
+}
+
+/// Compared with the index, only files added with `git add -N` show up as new
+/// in the working tree.
+const INTENT_TO_ADD_FILTER: &str = "--diff-filter=A";
+
+/// Whether the diff is of the index against HEAD, the usual staged changes.
+fn diffs_index(args: &Args) -> bool {
+    args.since.is_none() && args.stash.is_none() && args.staged_base.is_none()
+}
+
+/// Stage the files marked with `git add -N` in full, since the message
+/// describes them but `git commit` would leave them out. Only the files the
+/// diff covers: one left out by --include-ext or do_not_send wasn't described.
+fn stage_intent_to_add(args: &Args) -> Result<()> {
+    if !diffs_index(args) {
+        return Ok(());
+    }
+
+    let pathspecs = pathspecs(args);
+    let mut diff = vec!["diff", "--name-only", INTENT_TO_ADD_FILTER, "--"];
+    diff.extend(pathspecs.iter().map(String::as_str));
+    let files = git(&diff)?;
+    let files = files.lines().collect::<Vec<_>>();
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    eprintln!("➕ Staging intent-to-add files: {}", files.join(", "));
+    let mut add = vec!["add", "--"];
+    add.extend(&files);
+    git(&add)?;
+    Ok(())
+}
+
+/// Limit the diff to files with the --include-ext extensions, or to the
+/// files of one package, which were picked with them already.
+fn pathspecs(args: &Args) -> Vec<String> {
+    let mut pathspecs = if args.only_paths.is_empty() {
+        args.include_ext
+            .iter()
+            .map(|ext| format!(":(top)*.{}", ext.trim().trim_start_matches('.')))
+            .collect::<Vec<_>>()
+    } else {
+        args.only_paths.iter().map(|path| format!(":(top,literal){}", path)).collect()
+    };
+
+    if args.config.do_not_send_mode == Some(DoNotSendMode::Exclude) {
+        pathspecs.extend(do_not_send_pathspecs(args, ",exclude"));
+    }
+
+    pathspecs
+}
+
+/// The `do_not_send` patterns as globs from the repository root. Like
+/// .gitignore, a pattern without a slash matches in any directory.
+fn do_not_send_globs(args: &Args) -> Vec<String> {
+    args.config
+        .do_not_send
+        .iter()
+        .flatten()
+        .map(|pattern| {
+            let pattern = pattern.trim_start_matches('/');
+            let anywhere = if pattern.contains('/') { "" } else { "**/" };
+            format!("{}{}", anywhere, pattern)
+        })
+        .collect()
+}
+
+/// The `do_not_send` patterns as pathspecs.
+fn do_not_send_pathspecs(args: &Args, magic: &str) -> Vec<String> {
+    do_not_send_globs(args).iter().map(|glob| format!(":(top,glob{}){}", magic, glob)).collect()
+}
+
+/// Refuse to go on when the diff over `revisions` touches a `do_not_send`
+/// file, or in exclude mode say which files are being left out and return
+/// them.
+fn check_do_not_send<S: AsRef<std::ffi::OsStr>>(args: &Args, revisions: &[S], can_exclude: bool) -> Result<Vec<String>> {
+    let patterns = do_not_send_pathspecs(args, "");
+    if patterns.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let output = Command::new("git")
+        .arg("diff")
+        .arg("--name-only")
+        .args(revisions)
+        .arg("--")
+        .args(&patterns)
+        .output()
+        .context(GaiError::Git("Failed to execute git diff command".to_string()))?;
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(GaiError::Git(format!("git diff failed: {}", error.trim())).into());
+    }
+
+    let files = String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect::<Vec<_>>();
+    if files.is_empty() {
+        return Ok(files);
+    }
+
+    if can_exclude && args.config.do_not_send_mode == Some(DoNotSendMode::Exclude) {
+        eprintln!("⚠️ Not sending changes to {} (do_not_send)", files.join(", "));
+        return Ok(files);
+    }
+
+    Err(GaiError::Config(format!(
+        "The changes touch files that must not be sent to a provider (do_not_send): {}",
+        files.join(", ")
+    )).into())
+}
+
+/// Apply `do_not_send` to a diff that doesn't come from this repository,
+/// like a pull request's: in exclude mode drop the files it matches,
+/// otherwise refuse to go on.
+fn filter_do_not_send(args: &Args, diff: &str) -> Result<String> {
+    let patterns = do_not_send_globs(args);
+    if patterns.is_empty() {
+        return Ok(diff.to_string());
+    }
+
+    let mut kept = String::with_capacity(diff.len());
+    let mut files = Vec::new();
+    for section in file_sections(diff) {
+        let paths = section_paths(section);
+        match paths.iter().find(|path| patterns.iter().any(|pattern| glob_matches(pattern, path))) {
+            Some(path) => files.push(path.to_string()),
+            None => kept.push_str(section),
+        }
+    }
+    if files.is_empty() {
+        return Ok(kept);
+    }
+
+    if args.config.do_not_send_mode == Some(DoNotSendMode::Exclude) {
+        eprintln!("⚠️ Not sending changes to {} (do_not_send)", files.join(", "));
+        return Ok(kept);
+    }
+
+    Err(GaiError::Config(format!(
+        "The changes touch files that must not be sent to a provider (do_not_send): {}",
+        files.join(", ")
+    )).into())
+}
+
+/// Whether `path` matches `pattern` the way a `:(glob)` pathspec does: `*`
+/// and `?` stay within a directory, `**` crosses them, and matching a
+/// leading directory covers everything in it.
+fn glob_matches(pattern: &str, path: &str) -> bool {
+    fn matches(pattern: &[u8], path: &[u8]) -> bool {
+        match pattern {
+            [] => path.is_empty(),
+            [b'*', b'*', b'/', rest @ ..] => {
+                matches(rest, path) || path.iter().enumerate().any(|(i, &c)| c == b'/' && matches(rest, &path[i + 1..]))
+            }
+            [b'*', b'*', rest @ ..] => (0..=path.len()).any(|i| matches(rest, &path[i..])),
+            [b'*', rest @ ..] => (0..=path.len())
+                .take_while(|&i| i == 0 || path[i - 1] != b'/')
+                .any(|i| matches(rest, &path[i..])),
+            [b'?', rest @ ..] => matches!(path, [c, ..] if *c != b'/') && matches(rest, &path[1..]),
+            [c, rest @ ..] => path.first() == Some(c) && matches(rest, &path[1..]),
+        }
+    }
+
+    let (pattern, path) = (pattern.as_bytes(), path.as_bytes());
+    matches(pattern, path) || path.iter().enumerate().any(|(i, &c)| c == b'/' && matches(pattern, &path[..i]))
+}
+
+/// Paths, relative to the repository root, touched by the diff.
+fn changed_files(args: &Args) -> Result<Vec<String>> {
+    Ok(git_diff_report(args, "--name-only")?
+        .lines()
+        .map(str::to_string)
+        .collect())
+}
+
+/// Whether `path` looks like a test by where it lives (`tests/`, `spec/`,
+/// `__tests__/`) or by its name (`*_test.*`, `*.test.*`, `*.spec.*`, `test_*`).
+fn is_test_file(path: &str) -> bool {
+    let path = Path::new(path);
+    let in_test_dir = path
+        .parent()
+        .into_iter()
+        .flat_map(Path::components)
+        .any(|dir| matches!(dir.as_os_str().to_str(), Some("test" | "tests" | "spec" | "specs" | "__tests__")));
+
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    let stem = name.split('.').next().unwrap_or_default();
+    let named_as_test = stem.ends_with("_test")
+        || stem.ends_with("_spec")
+        || stem.starts_with("test_")
+        || name.contains(".test.")
+        || name.contains(".spec.");
+
+    in_test_dir || named_as_test
+}
+
+/// The --local message: a type from what kind of files changed, the package
+/// scope, and a description from how they changed.
+fn local_message(args: &Args, diff: &str) -> Result<String> {
+    let changes = diff_changes(diff);
+    if changes.is_empty() {
+        return Err(GaiError::Git("No staged changes found. Use 'git add' to stage your changes.".to_string()).into());
+    }
+
+    let files = changes.iter().map(|(_, file)| file.clone()).collect::<Vec<_>>();
+    let scope = if args.no_auto_scope { None } else { detect_package_scope(&files)? };
+
+    let mut kind = local_commit_type(&changes);
+    if let Some(types) = &args.config.allowed_types
+        && !types.iter().any(|allowed| allowed == kind)
+    {
+        kind = if types.iter().any(|allowed| allowed == "chore") { "chore" } else { &types[0] };
+    }
+
+    let subject = match scope {
+        Some(scope) => format!("{}({}): {}", kind, scope, local_description(&changes)),
+        None => format!("{}: {}", kind, local_description(&changes)),
+    };
+    Ok(subject)
+}
+
+/// The (status, path) of every file in `diff`, like `git diff --name-status`
+/// but for whatever diff was computed, e.g. by --diff-command.
+fn diff_changes(diff: &str) -> Vec<(char, String)> {
+    file_sections(diff)
+        .into_iter()
+        .filter(|section| section.starts_with("diff --git "))
+        .filter_map(|section| {
+            let header = section.lines().take_while(|line| !line.starts_with("@@")).collect::<Vec<_>>();
+            let has = |prefix: &str| header.iter().any(|line| line.starts_with(prefix));
+            let status = if has("new file mode") {
+                'A'
+            } else if has("deleted file mode") {
+                'D'
+            } else if has("rename to ") {
+                'R'
+            } else {
+                'M'
+            };
+
+            let path = header
+                .iter()
+                .find_map(|line| line.strip_prefix("+++ b/").or_else(|| line.strip_prefix("rename to ")))
+                .or_else(|| header.iter().find_map(|line| line.strip_prefix("--- a/")))
+                .or_else(|| header.first().and_then(|line| line.rsplit_once(" b/")).map(|(_, path)| path))?;
+            Some((status, path.to_string()))
+        })
+        .collect()
+}
+
+/// Build files whose changes are `build` commits, matched by file name.
+const BUILD_FILES: &[&str] = &[
+    "Cargo.toml", "Cargo.lock", "build.rs", "package.json", "package-lock.json", "yarn.lock", "pnpm-lock.yaml",
+    "go.mod", "go.sum", "pyproject.toml", "requirements.txt", "setup.py", "Makefile", "CMakeLists.txt", "Dockerfile",
+];
+
+/// Conventional commit type for a set of (status, path) changes.
+fn local_commit_type(changes: &[(char, String)]) -> &'static str {
+    let all = |matches: fn(&str) -> bool| changes.iter().all(|(_, file)| matches(file));
+    let name = |file: &str| Path::new(file).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+
+    if all(is_test_file) {
+        "test"
+    } else if all(|file| file.starts_with(".github/workflows/") || file.starts_with(".circleci/") || file == ".gitlab-ci.yml") {
+        "ci"
+    } else if changes.iter().all(|(_, file)| BUILD_FILES.contains(&name(file).as_str())) {
+        "build"
+    } else if all(|file| file.starts_with("docs/") || Path::new(file).extension().is_some_and(|ext| ext == "md" || ext == "rst" || ext == "adoc")) {
+        "docs"
+    } else if changes.iter().any(|(status, file)| *status == 'A' && !is_test_file(file)) {
+        "feat"
+    } else {
+        "chore"
+    }
+}
+
+/// "add parser.rs", "update README.md and CHANGELOG.md", "remove 4 files in src/".
+fn local_description(changes: &[(char, String)]) -> String {
+    let verb = match changes.first().map(|(status, _)| *status) {
+        Some(first) if changes.iter().all(|(status, _)| *status == first) => match first {
+            'A' => "add",
+            'D' => "remove",
+            'R' => "rename",
+            _ => "update",
+        },
+        _ => "update",
+    };
+
+    let mut names: Vec<String> = Vec::new();
+    for (_, file) in changes {
+        let name = Path::new(file).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_else(|| file.clone());
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    let object = match names.as_slice() {
+        [] => "files".to_string(),
+        [one] => one.clone(),
+        [rest @ .., last] if names.len() <= 3 => format!("{} and {}", rest.join(", "), last),
+        _ => {
+            let directory = changes[0].1.split_once('/').map(|(directory, _)| directory);
+            let shared = directory.filter(|dir| changes.iter().all(|(_, file)| file.starts_with(&format!("{}/", dir))));
+            match shared {
+                Some(directory) => format!("{} files in {}/", changes.len(), directory),
+                None => format!("{} files", changes.len()),
+            }
+        }
+    };
+
+    format!("{} {}", verb, object)
+}
+
+/// Manifests and lockfiles that `dependency_changes` knows how to read.
+const DEPENDENCY_FILES: &[&str] = &["Cargo.toml", "Cargo.lock", "package.json", "package-lock.json"];
+
+/// A package whose version changed; a missing version means it was added or removed.
+#[derive(Debug, PartialEq)]
+struct DependencyChange {
+    name: String,
+    old: Option<String>,
+    new: Option<String>,
+}
+
+/// A `chore(deps)` message for a change that only touches dependency
+/// versions, or `None` when anything else changed.
+fn dependency_message(args: &Args) -> Result<Option<String>> {
+    if let Some(types) = &args.config.allowed_types
+        && !types.iter().any(|allowed| allowed == "chore")
+    {
+        return Ok(None);
+    }
+
+    let diff = match &args.diff_command {
+        Some(command) => command_diff(args, command)?,
+        None => git_diff_report(args, "--unified=1")?,
+    };
+    Ok(dependency_changes(&diff).map(|changes| dependency_message_text(&changes)))
+}
+
+/// Version changes in a diff of dependency files only. Manifest entries win
+/// the list, with exact versions from the lockfile when it has them; a
+/// lockfile-only change such as `cargo update` lists every bumped package.
+fn dependency_changes(diff: &str) -> Option<Vec<DependencyChange>> {
+    let mut manifest: Vec<DependencyChange> = Vec::new();
+    let mut lock: Vec<DependencyChange> = Vec::new();
+    let record = |changes: &mut Vec<DependencyChange>, name: &str, sign: char, version: &str| {
+        let index = match changes.iter().position(|change| change.name == name) {
+            Some(index) => index,
+            None => {
+                changes.push(DependencyChange { name: name.to_string(), old: None, new: None });
+                changes.len() - 1
+            }
+        };
+        let slot = if sign == '-' { &mut changes[index].old } else { &mut changes[index].new };
+        *slot = Some(version.to_string());
+    };
+
+    let mut file = "";
+    let mut in_hunks = false;
+    let mut package: Option<String> = None;
+
+    for line in diff.lines() {
+        if let Some(rest) = line.strip_prefix("diff --git ") {
+            let path = split_diff_git_paths(rest).1;
+            file = path.rsplit('/').next().unwrap_or(path);
+            if !DEPENDENCY_FILES.contains(&file) {
+                return None;
+            }
+            (in_hunks, package) = (false, None);
+            continue;
+        }
+        if line.starts_with("@@") {
+            in_hunks = true;
+            continue;
+        }
+        if !in_hunks || line.starts_with('\\') {
+            continue;
+        }
+
+        let sign = line.chars().next().unwrap_or(' ');
+        let content = line.get(1..).unwrap_or_default().trim();
+        let changed = sign == '+' || sign == '-';
+
+        match file {
+            "Cargo.lock" => {
+                if let Some(name) = toml_string(content, "name") {
+                    package = Some(name.to_string());
+                } else if let Some(version) = toml_string(content, "version")
+                    && changed
+                    && let Some(name) = &package
+                {
+                    record(&mut lock, name, sign, version);
+                }
+            }
+            "package-lock.json" => {
+                if let Some(key) = content.strip_suffix("{").and_then(|key| json_key(key.trim_end())) {
+                    package = Some(key.rsplit("node_modules/").next().unwrap_or(key).to_string());
+                } else if let Some(("version", version)) = json_entry(content)
+                    && changed
+                    && let Some(name) = package.as_deref().filter(|name| !name.is_empty())
+                {
+                    record(&mut lock, name, sign, version);
+                }
+            }
+            _ if !changed || content.is_empty() || content.starts_with('#') => {}
+            "Cargo.toml" => {
+                let (name, value) = content.split_once('=')?;
+                let (name, value) = (name.trim(), value.trim());
+                let version = match value.strip_prefix('{') {
+                    Some(table) => table.split(',').find_map(|field| toml_string(field.trim().trim_end_matches('}').trim(), "version"))?,
+                    None => value.strip_prefix('"')?.strip_suffix('"')?,
+                };
+                if ["version", "rust-version", "edition"].contains(&name) {
+                    return None;
+                }
+                record(&mut manifest, name, sign, version);
+            }
+            _ => {
+                let (name, version) = json_entry(content)?;
+                let looks_like_version = version.starts_with(|c: char| c.is_ascii_digit() || "^~<>=*".contains(c));
+                if !looks_like_version || ["name", "version"].contains(&name) {
+                    return None;
+                }
+                record(&mut manifest, name, sign, version);
+            }
+        }
+    }
+
+    let changes = if manifest.is_empty() {
+        lock
+    } else {
+        manifest
+            .into_iter()
+            .map(|change| match lock.iter().position(|locked| locked.name == change.name) {
+                Some(index) => lock.swap_remove(index),
+                None => change,
+            })
+            .collect()
+    };
+    let changes = changes.into_iter().filter(|change| change.old != change.new).collect::<Vec<_>>();
+
+    (!changes.is_empty()).then_some(changes)
+}
+
+/// The value of a `key = "value"` TOML line.
+fn toml_string<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let value = line.strip_prefix(key)?.trim_start().strip_prefix('=')?.trim();
+    value.strip_prefix('"')?.strip_suffix('"')
+}
+
+/// The key of a `"key":` JSON object line.
+fn json_key(line: &str) -> Option<&str> {
+    line.strip_suffix(':')?.trim_end().strip_prefix('"')?.strip_suffix('"')
+}
+
+/// The key and value of a `"key": "value",` JSON line.
+fn json_entry(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.trim_end_matches(',').split_once(':')?;
+    let key = key.trim().strip_prefix('"')?.strip_suffix('"')?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    Some((key, value))
+}
+
+fn dependency_message_text(changes: &[DependencyChange]) -> String {
+    let describe = |change: &DependencyChange| match (&change.old, &change.new) {
+        (Some(old), Some(new)) => format!("bump {} from {} to {}", change.name, old, new),
+        (None, Some(new)) => format!("add {} {}", change.name, new),
+        (Some(old), _) => format!("remove {} {}", change.name, old),
+        (None, None) => format!("update {}", change.name),
+    };
+
+    let description = match changes {
+        [one] => return format!("chore(deps): {}", describe(one)),
+        [rest @ .., last] if changes.len() <= 3 && changes.iter().all(|change| change.old.is_some() && change.new.is_some()) => {
+            let names = rest.iter().map(|change| change.name.as_str()).collect::<Vec<_>>();
+            format!("bump {} and {}", names.join(", "), last.name)
+        }
+        _ => format!("update {} dependencies", changes.len()),
+    };
+
+    let list = changes.iter().map(|change| format!("- {}", describe(change))).collect::<Vec<_>>();
+    format!("chore(deps): {}\n\n{}", description, list.join("\n"))
+}
+
+/// Find the single monorepo package (nearest directory below the repository
+/// root with a `Cargo.toml` or `package.json`) containing every changed file.
+fn detect_package_scope(files: &[String]) -> Result<Option<String>> {
+    // Outside git, e.g. with a diff_command, paths are taken to be relative to here
+    let root = repository_root().unwrap_or_default();
+
+    let mut scope: Option<String> = None;
+    for file in files {
+        let package = package_dir(&root, file)
+            .and_then(|dir| dir.file_name().map(|name| name.to_string_lossy().to_string()));
+
+        match (package, &scope) {
+            (None, _) => return Ok(None),
+            (Some(package), None) => scope = Some(package),
+            (Some(package), Some(current)) if &package != current => return Ok(None),
+            _ => {}
+        }
+    }
+
+    Ok(scope)
+}
+
+/// The nearest directory containing `file` with a `Cargo.toml` or
+/// `package.json`, not counting the repository root.
+fn package_dir(root: &Path, file: &str) -> Option<PathBuf> {
+    Path::new(file)
+        .ancestors()
+        .skip(1)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .find(|dir| root.join(dir).join("Cargo.toml").is_file() || root.join(dir).join("package.json").is_file())
+        .map(Path::to_path_buf)
+}
+
+/// Look for a recent commit whose inverse has the same patch ID as `diff`.
+fn detect_revert(diff: &str) -> Result<Option<(String, String)>> {
+    let Some(staged_id) = patch_id(diff) else {
+        return Ok(None);
+    };
+
+    // The inverse of every recent commit in one log, so one patch-id run
+    // covers them all. -R swaps the path prefixes too, which would change
+    // the patch IDs, so they're swapped back
+    let output = Command::new("git")
+        .args(["log", "-n", &REVERT_SEARCH_DEPTH.to_string(), "--no-merges", "-R", "-p", "--no-color", "--no-ext-diff"])
+        .args(["--src-prefix=b/", "--dst-prefix=a/", "--format=commit %H%n%h %s"])
+        .output()
+        .context(GaiError::Git("Failed to execute git log command".to_string()))?;
+
+    // A repository without commits has nothing to revert
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    let log = String::from_utf8_lossy(&output.stdout);
+    let Some((_, sha)) = patch_ids(&log).into_iter().find(|(id, _)| *id == staged_id) else {
+        return Ok(None);
+    };
+
+    let header = format!("commit {}", sha);
+    let summary = log.lines().skip_while(|line| *line != header).nth(1).unwrap_or_default();
+    let (short_sha, subject) = summary.split_once(' ').unwrap_or((summary, ""));
+    Ok(Some((short_sha.to_string(), subject.to_string())))
+}
+
+fn patch_id(diff: &str) -> Option<String> {
+    patch_ids(diff).into_iter().next().map(|(id, _)| id)
+}
+
+/// `git patch-id --stable` of `patches`: a (patch ID, commit) pair for each
+/// commit in a `git log -p`, or a single one with a zero commit for a diff.
+fn patch_ids(patches: &str) -> Vec<(String, String)> {
+    let child = Command::new("git")
+        .args(["patch-id", "--stable"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn();
+    let Ok(output) = child.and_then(|child| communicate(child, patches.to_string())) else {
+        return Vec::new();
+    };
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(id, commit)| (id.to_string(), commit.trim().to_string()))
+        .collect()
+}
+
+/// Write `input` to the stdin of `child` from a thread of its own while
+/// reading its output, so one that prints a lot before it has read
+/// everything can't deadlock on a full pipe. Stopping reading early, having
+/// decided already, is fine.
+fn communicate(mut child: std::process::Child, input: String) -> std::io::Result<std::process::Output> {
+    let writer = child.stdin.take().map(|mut stdin| {
+        std::thread::spawn(move || match stdin.write_all(input.as_bytes()) {
+            Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => Err(e),
+            _ => Ok(()),
+        })
+    });
+
+    let output = child.wait_with_output()?;
+    if let Some(writer) = writer {
+        writer.join().expect("the stdin writer doesn't panic")?;
+    }
+    Ok(output)
+}
+
+fn get_branch_diff(args: &Args) -> Result<String> {
+    ensure_git_repository()?;
+
+    let output = Command::new("git")
+        .args(["merge-base", &args.base, "HEAD"])
+        .output()
+        .context(GaiError::Git("Failed to execute git merge-base command".to_string()))?;
+
+    if !output.status.success() {
+        return Err(GaiError::Git(format!("Could not find a merge base between {} and HEAD. Use --base to pick the base branch.", args.base)).into());
+    }
+
+    let merge_base = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    check_do_not_send(args, &[merge_base.as_str(), "HEAD"], true)?;
+    let diff = run_git_diff(args, &[merge_base.as_str(), "HEAD"])?;
+
+    if diff.is_empty() {
+        return Err(GaiError::Git(format!("No changes between {} and HEAD.", args.base)).into());
+    }
+
+    Ok(diff)
+}
+
+fn repository_root() -> Result<PathBuf> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .output()
+        .context(GaiError::Git("Failed to execute git command. Is git installed?".to_string()))?;
+
+    if !output.status.success() {
+        return Err(GaiError::Git("Not inside a git repository".to_string()).into());
+    }
+
+    Ok(PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()))
+}
+
+fn ensure_git_repository() -> Result<()> {
+    // Check if we're in a git repository
+    let git_check = Command::new("git")
+        .args(["rev-parse", "--is-inside-work-tree"])
+        .output()
+        .context(GaiError::Git("Failed to execute git command. Is git installed?".to_string()))?;
+
+    if !git_check.status.success() {
+        return Err(GaiError::Git("Not inside a git repository".to_string()).into());
+    }
+
+    Ok(())
+}
+
+/// Run `git diff` over the given revisions with the user's diff options.
+fn run_git_diff<S: AsRef<std::ffi::OsStr>>(args: &Args, revisions: &[S]) -> Result<String> {
+    let mut command = Command::new("git");
+    command.arg("diff");
+    if let Some(context) = args.context {
+        command.arg(format!("-U{}", context));
+    }
+    if args.function_context {
+        command.arg("--function-context");
+    }
+    if let Some(algorithm) = args.diff_algorithm {
+        let name = algorithm.to_possible_value().expect("diff algorithms are never skipped");
+        command.arg(format!("--diff-algorithm={}", name.get_name()));
+    }
+    command.args(revisions).arg("--").args(pathspecs(args));
+
+    let output = command
+        .output()
+        .context(GaiError::Git("Failed to execute git diff command".to_string()))?;
+
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(GaiError::Git(format!("git diff failed: {}", error.trim())).into());
+    }
+
+    // Convert output to string
+    String::from_utf8(output.stdout)
+        .context(GaiError::Git("Failed to parse git diff output as UTF-8".to_string()))
+}
+
+/// Subjects git writes itself, which commitlint lets through as well.
+const GIT_SUBJECT_PREFIXES: &[&str] = &["Merge ", "Revert \"", "fixup! ", "squash! ", "amend! "];
+
+/// Check the message in `path` (`-` for stdin) with the rules and
+/// `validate_command` the generator uses, for a commit-msg hook.
+fn validate_only(args: &Args, path: &Path) -> Result<()> {
+    let raw = if path == Path::new("-") {
+        std::io::read_to_string(std::io::stdin()).context(GaiError::Io("Failed to read the message from stdin".to_string()))?
+    } else {
+        fs::read_to_string(path).with_context(|| GaiError::Io(format!("Failed to read {}", path.display())))?
+    };
+    let message = commit_message_text(&raw, &comment_prefix());
+    let subject = message.lines().next().unwrap_or_default();
+
+    if message.is_empty() {
+        return Err(GaiError::Config("The commit message is empty".to_string()).into());
+    }
+    if GIT_SUBJECT_PREFIXES.iter().any(|prefix| subject.starts_with(prefix)) {
+        return Ok(());
+    }
+
+    let rules = message_rules(args)?;
+    let mut problems = rules.check(&message);
+    // Without a type list the rules don't look at the format
+    if rules.types.is_none() && lint::parse_subject(subject).is_none() {
+        problems.push("the subject must follow the format <type>[optional scope]: <description>".to_string());
+    }
+    if let Some(output) = validation_failure(args, &message)? {
+        problems.push(format!("validate_command rejected it: {}", output));
+    }
+
+    if !problems.is_empty() {
+        let list = problems.iter().map(|problem| format!("\n- {}", problem)).collect::<String>();
+        return Err(GaiError::Config(format!("The commit message breaks the commit rules:{}", list)).into());
+    }
+
+    if !args.quiet {
+        println!("✅ The commit message follows the commit rules");
+    }
+    Ok(())
+}
+
+/// A message as git will commit it: without lines starting with `comment`, or anything
+/// below the scissors line `git commit --verbose` adds.
+fn commit_message_text(raw: &str, comment: &str) -> String {
+    let scissors = format!("{} ------------------------ >8 ------------------------", comment);
+    let message = raw
+        .lines()
+        .take_while(|line| !line.starts_with(&scissors))
+        .filter(|line| !line.starts_with(comment))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    message.trim().to_string()
+}
+
+/// What git starts comment lines in a message file with: `core.commentChar`,
+/// or `#`. With `auto` git picks one per message and doesn't say which, so
+/// `#` is the best guess then too.
+fn comment_prefix() -> String {
+    git(&["config", "--get", "core.commentChar"])
+        .map(|value| value.trim_end_matches(['\r', '\n']).to_string())
+        .ok()
+        .filter(|value| !value.is_empty() && value != "auto")
+        .unwrap_or_else(|| "#".to_string())
+}
+
+/// Run the configured `validate_command` on a message. Returns what the
+/// validator printed when it rejects the message, for the model to act on.
+fn validation_failure(args: &Args, message: &str) -> Result<Option<String>> {
+    let Some(command) = &args.config.validate_command else {
+        return Ok(None);
+    };
+
+    let child = shell(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| GaiError::Config(format!("Failed to run validate_command: {}", command)))?;
+    let output = communicate(child, message.to_string())
+        .with_context(|| GaiError::Config(format!("Failed to run validate_command: {}", command)))?;
+
+    if output.status.success() {
+        return Ok(None);
+    }
+
+    let report = format!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+    Ok(Some(match report.trim() {
+        "" => format!("validate_command exited with {}", output.status),
+        report => report.to_string(),
+    }))
+}
+
+/// Version of the JSON that `plugins` receive, bumped on incompatible changes.
+const PLUGIN_API_VERSION: u32 = 1;
+
+/// Pass the message through each configured plugin in turn. A plugin reads
+/// `{"version", "message", "diff", "provider", "model", "generated"}` on
+/// stdin, then exits 0 to accept the message, or to replace it with what it
+/// printed; exits 1 to veto it, with its output as the reason; and any other
+/// exit code is a failure of the plugin itself. `sent_diff` is the diff the
+/// model was given, `None` for a message that wasn't generated.
+fn run_plugins(args: &Args, mut message: String, sent_diff: Option<&str>) -> Result<String> {
+    let Some(plugins) = args.config.plugins.as_ref().filter(|plugins| !plugins.is_empty()) else {
+        return Ok(message);
+    };
+
+    // A message of the user's own still comes with the diff as it would be sent
+    let diff = match sent_diff {
+        Some(diff) => diff.to_string(),
+        None => get_git_diff(args)?.0,
+    };
+
+    for plugin in plugins {
+        let input = json!({
+            "version": PLUGIN_API_VERSION,
+            "message": message,
+            "diff": diff,
+            "provider": args.provider.to_possible_value().expect("providers are never skipped").get_name(),
+            "model": args.model,
+            "generated": sent_diff.is_some(),
+        });
+
+        let child = shell(plugin)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| GaiError::Config(format!("Failed to run plugin: {}", plugin)))?;
+        let output = communicate(child, input.to_string())
+            .with_context(|| GaiError::Config(format!("Failed to run plugin: {}", plugin)))?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        match output.status.code() {
+            Some(0) => {
+                if !stderr.trim().is_empty() {
+                    eprintln!("{}", stderr.trim_end());
+                }
+                if !stdout.trim().is_empty() {
+                    message = clean_message(&stdout);
+                }
+            }
+            Some(1) => {
+                let reason = format!("{}{}", stdout, stderr);
+                let reason = if reason.trim().is_empty() { "no reason given" } else { reason.trim() };
+                return Err(GaiError::Config(format!("Plugin '{}' vetoed the message: {}", plugin, reason)).into());
+            }
+            _ => {
+                return Err(GaiError::Config(format!("Plugin '{}' failed ({}): {}", plugin, output.status, stderr.trim())).into());
+            }
+        }
+    }
+
+    Ok(message)
+}
+
+/// A command that runs `command` through the platform shell.
+fn shell(command: &str) -> Command {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command);
+    shell
+}
+
+/// Run a user supplied shell command with `input` on stdin and return its
+/// stdout, failing if it exits non-zero.
+fn run_shell(command: &str, input: &str) -> Result<String> {
+    let child = shell(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .with_context(|| GaiError::Config(format!("Failed to run command: {}", command)))?;
+
+    // A filter like sed prints as it reads, so its output has to be drained meanwhile
+    let output = communicate(child, input.to_string())
+        .with_context(|| GaiError::Config(format!("Failed to run command: {}", command)))?;
+
+    if !output.status.success() {
+        return Err(GaiError::Config(format!("Command '{}' exited with {}", command, output.status)).into());
+    }
+
+    String::from_utf8(output.stdout)
+        .with_context(|| GaiError::Config(format!("Command '{}' printed invalid UTF-8", command)))
+}
+
+/// Pipe `text` into the first clipboard tool that is installed.
+fn copy_to_clipboard(text: &str) -> Result<()> {
+    let tools: &[&[&str]] = if cfg!(windows) {
+        &[&["clip"]]
+    } else if cfg!(target_os = "macos") {
+        &[&["pbcopy"]]
+    } else {
+        &[&["wl-copy"], &["xclip", "-selection", "clipboard"], &["xsel", "--clipboard", "--input"]]
+    };
+
+    for tool in tools {
+        let Ok(mut child) = Command::new(tool[0])
+            .args(&tool[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        else {
+            continue;
+        };
+
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(text.as_bytes());
+        }
+        if child.wait().is_ok_and(|status| status.success()) {
+            return Ok(());
+        }
+    }
+
+    Err(GaiError::Io("Could not copy to the clipboard; no working clipboard tool was found".to_string()).into())
+}
+
+/// Read the clipboard with the first clipboard tool that is installed.
+fn paste_from_clipboard() -> Result<String> {
+    let tools: &[&[&str]] = if cfg!(windows) {
+        &[&["powershell", "-NoProfile", "-Command", "Get-Clipboard"]]
+    } else if cfg!(target_os = "macos") {
+        &[&["pbpaste"]]
+    } else {
+        &[&["wl-paste", "--no-newline"], &["xclip", "-selection", "clipboard", "-o"], &["xsel", "--clipboard", "--output"]]
+    };
+
+    for tool in tools {
+        let Ok(output) = Command::new(tool[0]).args(&tool[1..]).stderr(Stdio::null()).output() else {
+            continue;
+        };
+        if output.status.success() {
+            return Ok(String::from_utf8_lossy(&output.stdout).to_string());
+        }
+    }
+
+    Err(GaiError::Io("Could not read the clipboard; no working clipboard tool was found".to_string()).into())
+}
+
+/// Run a git command and return its stdout, failing with git's stderr.
+fn git(args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .context(GaiError::Git("Failed to execute git command. Is git installed?".to_string()))?;
+
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(GaiError::Git(format!("git {} failed: {}", args.first().unwrap_or(&""), error.trim())).into());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// List logged messages, oldest first, optionally filtered by `grep`.
+fn print_history(grep: Option<&str>) -> Result<()> {
+    let grep = grep.map(str::to_lowercase);
+
+    for entry in history::load()? {
+        if let Some(term) = &grep
+            && !entry.message.to_lowercase().contains(term)
+        {
+            continue;
+        }
+
+        let sha = entry.sha.as_deref().map(|sha| &sha[..sha.len().min(7)]).unwrap_or("uncommitted");
+        println!("📅 {}  {}  {}", history::format_date(entry.timestamp), entry.model, sha);
+        println!("{}\n", entry.message);
+    }
+
+    Ok(())
+}
+
+/// Modes and options wrappers can detect with --capabilities.
+const FEATURES: &[&str] = &[
+    "generate", "commit", "explain", "pr", "interactive", "auto-split", "batch", "watch", "stash",
+    "json-schema", "verify-model", "fix-scope", "fix-type", "score", "max-cost", "history-hook", "self-update", "local",
+    "pr-url",
+];
+
+fn capabilities() -> Value {
+    let providers = Provider::value_variants()
+        .iter()
+        .map(|provider| {
+            let name = provider.to_possible_value().expect("providers are never skipped");
+            let mut models = cost::PRICES
+                .iter()
+                .map(|(model, _, _)| *model)
+                .filter(|model| provider.offers_model(model))
+                .collect::<Vec<_>>();
+            if !models.contains(&provider.default_model()) {
+                models.insert(0, provider.default_model());
+            }
+
+            json!({
+                "name": name.get_name(),
+                "default_model": provider.default_model(),
+                "known_models": models,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "providers": providers,
+        "features": FEATURES,
+    })
+}
+
+/// Print a fresh suggestion each time the index settles after a change.
+async fn watch_index(args: &Args) -> Result<()> {
+    ensure_git_repository()?;
+    let index = PathBuf::from(git(&["rev-parse", "--git-path", "index"])?.trim());
+    let modified = || fs::metadata(&index).and_then(|m| m.modified()).ok();
+
+    println!("👀 Watching the index; stage changes to update the suggestion (Ctrl-C to stop)");
+
+    let mut last = None;
+    loop {
+        let current = modified();
+        if current != last {
+            // Wait for a burst of `git add` calls to finish before generating
+            tokio::time::sleep(WATCH_INTERVAL).await;
+            if modified() != current {
+                continue;
+            }
+            last = current;
+
+            if let Err(e) = confirm_run_cost(args, &estimated_diff(args)) {
+                eprintln!("\n⚠️ {:#}", e);
+                continue;
+            }
+            match generate_commit_message(args).await {
+                Ok(message) => println!("\n📝 {}", message),
+                Err(e) if e.is::<PromptShown>() => return Err(e),
+                Err(e) => eprintln!("\n⚠️ {:#}", e),
+            }
+        }
+
+        tokio::time::sleep(WATCH_INTERVAL).await;
+    }
+}
+
+/// Repeatedly pick a subset of the staged files, then generate a message
+/// for and commit just those, until nothing staged is left.
+async fn commit_interactively(args: &Args) -> Result<()> {
+    ensure_git_repository()?;
+
+    loop {
+        let files = changed_files(args)?;
+        if files.is_empty() {
+            println!("✅ No staged changes left");
+            return Ok(());
+        }
+
+        let selected = MultiSelect::new()
+            .with_prompt("Select the files for the next commit (space to toggle, enter to confirm)")
+            .items(&files)
+            .interact()
+            .context(GaiError::Io("Failed to read the file selection".to_string()))?;
+
+        if selected.is_empty() {
+            println!("No files selected; leaving the remaining changes staged");
+            return Ok(());
+        }
+
+        let unselected = files
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !selected.contains(i))
+            .map(|(_, file)| file.as_str())
+            .collect::<Vec<_>>();
+
+        // Park the full index so the other files can be restaged afterwards
+        let parked = ParkedIndex::park()?;
+
+        if !unselected.is_empty() {
+            let mut reset = vec!["reset", "-q", "--"];
+            reset.extend(&unselected);
+            git(&reset)?;
+        }
+
+        commit_staged(args).await?;
+        parked.restore()?;
+    }
+}
+
+/// Ask the model for the conventional type of every staged hunk, then
+/// commit the hunks of each type separately, in order of first appearance.
+/// Every message is written before the first commit.
+async fn commit_split_by_type(args: &Args) -> Result<()> {
+    ensure_git_repository()?;
+
+    let excluded = check_do_not_send(args, &["--cached"], true)?;
+    let diff = git(&["diff", "--cached", "--binary", "--no-color", "--no-ext-diff", "--src-prefix=a/", "--dst-prefix=b/"])?;
+    if diff.trim().is_empty() {
+        return Err(GaiError::Git("No staged changes found. Use 'git add' to stage changes first.".to_string()).into());
+    }
+
+    let hunks = split_hunks(&diff);
+    let types = classify_hunks(args, &hunks, &excluded).await?;
+
+    let mut groups: Vec<(&str, Vec<&Hunk>)> = Vec::new();
+    for (hunk, kind) in hunks.iter().zip(&types) {
+        match groups.iter_mut().find(|(group, _)| group == kind) {
+            Some((_, members)) => members.push(hunk),
+            None => groups.push((kind, vec![hunk])),
+        }
+    }
+
+    if groups.len() == 1 {
+        println!("🔀 Every hunk looks like '{}'; making a single commit", groups[0].0);
+        return commit_staged(args).await;
+    }
+
+    if !args.quiet {
+        for (kind, members) in &groups {
+            println!("🔀 {}: {} hunk(s)", kind, members.len());
+        }
+    }
+
+    // Park the full index, then stage each group on top of the one before,
+    // the way the commits will land, and describe it against that
+    let parked = ParkedIndex::park()?;
+    let mut base = git(&["rev-parse", "-q", "--verify", "HEAD^{tree}"])
+        .map(|tree| tree.trim().to_string())
+        .unwrap_or_else(|_| EMPTY_TREE.to_string());
+    git(&["read-tree", &base])?;
+
+    let mut applied = Vec::new();
+    let mut steps = Vec::new();
+    for (kind, members) in &groups {
+        stage_hunks(members, &mut applied).with_context(|| format!("Failed to stage the '{}' changes; nothing was committed", kind))?;
+        let tree = git(&["write-tree"])?.trim().to_string();
+        let group_args = Args { staged_base: Some(base), ..args.clone() };
+        let message = generate_and_record(&group_args).await.context("Nothing was committed")?;
+        base = tree.clone();
+        steps.push((kind, tree, group_args, message));
+    }
+
+    for (committed, (kind, tree, group_args, message)) in steps.iter().enumerate() {
+        let result = git(&["read-tree", tree]).and_then(|_| create_commit(group_args, message));
+
+        if let Err(e) = result {
+            // Restoring the tree restages everything not committed yet
+            parked.restore()?;
+            return Err(e.context(format!(
+                "Failed to commit the '{}' changes; {} of {} groups were committed and the rest are still staged",
+                kind,
+                committed,
+                steps.len()
+            )));
+        }
+
+        add_generation_note(group_args);
+        println!("✅ Committed {}: \"{}\"", kind, message);
+    }
+
+    parked.restore()
+}
+
+/// Group the staged files by monorepo package and commit each package on its
+/// own, files outside any package last. Every message is written before the
+/// first commit, so a failed request leaves nothing half committed.
+async fn commit_split_by_package(args: &Args) -> Result<()> {
+    ensure_git_repository()?;
+
+    let root = repository_root()?;
+    let mut groups: Vec<(Option<PathBuf>, Vec<String>)> = Vec::new();
+    for file in changed_files(args)? {
+        let package = package_dir(&root, &file);
+        match groups.iter_mut().find(|(group, _)| *group == package) {
+            Some((_, files)) => files.push(file),
+            None => groups.push((package, vec![file])),
+        }
+    }
+    groups.sort_by_key(|(package, _)| package.is_none());
+
+    let label = |package: &Option<PathBuf>| match package {
+        Some(dir) => dir.display().to_string(),
+        None => "files outside any package".to_string(),
+    };
+
+    match groups.as_slice() {
+        [] => return Err(GaiError::Git("No staged changes found. Use 'git add' to stage changes first.".to_string()).into()),
+        [(package, _)] => {
+            println!("📦 Every change is in {}; making a single commit", label(package));
+            return commit_staged(args).await;
+        }
+        _ => {}
+    }
+
+    if !args.quiet {
+        for (package, files) in &groups {
+            println!("📦 {}: {} file(s)", label(package), files.len());
+        }
+    }
+
+    let package_args = groups
+        .iter()
+        .map(|(_, files)| Args { only_paths: files.clone(), ..args.clone() })
+        .collect::<Vec<_>>();
+    let messages = futures::future::join_all(package_args.iter().map(generate_and_record))
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>>>()
+        .context("Nothing was committed")?;
+
+    // Only now that every message is in: otherwise the first commit would
+    // take every intent-to-add file along
+    stage_intent_to_add(args)?;
+
+    // Park the full index so each package can be staged from it in turn
+    let parked = ParkedIndex::park()?;
+    let all_files = groups.iter().flat_map(|(_, files)| files).collect::<Vec<_>>();
+
+    for (committed, ((package, files), (package_args, message))) in groups.iter().zip(package_args.iter().zip(&messages)).enumerate() {
+        let result = parked.restore().and_then(|_| {
+            let mut reset = vec!["reset", "-q", "--"];
+            reset.extend(all_files.iter().filter(|file| !files.contains(file)).map(|file| file.as_str()));
+            git(&reset)?;
+            create_commit(package_args, message)
+        });
+
+        if let Err(e) = result {
+            // Restoring the tree restages everything not committed yet
+            parked.restore()?;
+            return Err(e.context(format!(
+                "Failed to commit {}; {} of {} packages were committed and the rest are still staged",
+                label(package),
+                committed,
+                groups.len()
+            )));
+        }
+
+        add_generation_note(package_args);
+        println!("✅ Committed {}: \"{}\"", label(package), message);
+    }
+
+    parked.restore()
+}
+
+/// One hunk of a diff along with its file header, so it can be applied on
+/// its own. Sections without hunks (binary files, renames, mode changes)
+/// are kept whole.
+struct Hunk {
+    header: String,
+    body: String,
+}
+
+impl Hunk {
+    /// The file's path after the change.
+    fn path(&self) -> &str {
+        let lines = || self.header.lines();
+        lines()
+            .find_map(|line| line.strip_prefix("+++ b/"))
+            .or_else(|| lines().find_map(|line| line.strip_prefix("--- a/")))
+            .or_else(|| lines().find_map(|line| line.strip_prefix("rename to ")))
+            .or_else(|| lines().next().and_then(|line| line.rsplit_once(" b/")).map(|(_, path)| path))
+            .unwrap_or_default()
+    }
+
+    /// A header naming only the new path, for further hunks of a file whose
+    /// rename or mode change is already in the index.
+    fn plain_header(&self) -> String {
+        let new = self.header.lines().find_map(|line| line.strip_prefix("+++ ")).unwrap_or_default();
+        let old = new.replacen("b/", "a/", 1);
+        format!("diff --git {} {}\n--- {}\n+++ {}\n", old, new, old, new)
+    }
+
+    /// The hunk as the model gets to see it: binary data is summed up in a
+    /// line, and the changes to `excluded` files are left out.
+    fn prompt_text(&self, excluded: &[String]) -> String {
+        let header = match self.header.find("\nGIT binary patch\n") {
+            Some(at) => format!("{}Binary files differ\n", &self.header[..at + 1]),
+            None => self.header.clone(),
+        };
+
+        if excluded.iter().any(|file| file == self.path()) {
+            format!("{}[changes not sent (do_not_send)]\n", header)
+        } else {
+            format!("{}{}", header, self.body)
+        }
+    }
+}
+
+/// Each file's part of `diff`, cut just before the next file header so a
+/// binary patch keeps the blank line it has to end with.
+fn file_sections(diff: &str) -> Vec<&str> {
+    let mut starts = diff.match_indices("\ndiff --git ").map(|(i, _)| i + 1).collect::<Vec<_>>();
+    starts.insert(0, 0);
+    starts.push(diff.len());
+
+    starts
+        .windows(2)
+        .map(|range| &diff[range[0]..range[1]])
+        .filter(|section| !section.trim().is_empty())
+        .collect()
+}
+
+/// The paths a file's diff section names, before and after the change.
+fn section_paths(section: &str) -> Vec<&str> {
+    let header = section.lines().take_while(|line| !line.starts_with("@@"));
+    let mut paths = header
+        .filter_map(|line| {
+            ["--- a/", "+++ b/", "rename from ", "rename to ", "copy from ", "copy to "]
+                .iter()
+                .find_map(|prefix| line.strip_prefix(prefix))
+        })
+        .collect::<Vec<_>>();
+
+    // Binary and mode-only changes only name their file on the first line
+    if let Some((_, path)) = section.lines().next().and_then(|line| line.rsplit_once(" b/")) {
+        paths.push(path);
+    }
+    paths
+}
+
+fn split_hunks(diff: &str) -> Vec<Hunk> {
+    let mut hunks = Vec::new();
+
+    for section in file_sections(diff) {
+        let section = if section.ends_with('\n') { section.to_string() } else { format!("{}\n", section) };
+
+        let Some(first) = section.find("\n@@ ") else {
+            hunks.push(Hunk {
+                header: section,
+                body: String::new(),
+            });
+            continue;
+        };
+        let (header, rest) = section.split_at(first + 1);
+
+        let mut starts = rest.match_indices("\n@@ ").map(|(i, _)| i + 1).collect::<Vec<_>>();
+        starts.insert(0, 0);
+        starts.push(rest.len());
+
+        for range in starts.windows(2) {
+            hunks.push(Hunk {
+                header: header.to_string(),
+                body: rest[range[0]..range[1]].to_string(),
+            });
+        }
+    }
+
+    hunks
+}
+
+async fn classify_hunks(args: &Args, hunks: &[Hunk], excluded: &[String]) -> Result<Vec<String>> {
+    let types = message_rules(args)?
+        .types
+        .unwrap_or_else(|| ["feat", "fix", "docs", "style", "refactor", "perf", "test", "build", "ci", "chore"].map(str::to_string).to_vec());
+
+    let mut content = format!(
+        "Classify each of the following {} diff hunks by the conventional commit type it belongs to, using only these types: {}. \
+         Respond with only a JSON object of the form {{\"types\": [\"...\"]}} containing exactly {} types, in the same order as the hunks.",
+        hunks.len(),
+        types.join(", "),
+        hunks.len()
+    );
+
+    // Like any other diff, the hunks share --max-diff-bytes, each cut to the
+    // same number of lines
+    let sections = hunks
+        .iter()
+        .enumerate()
+        .map(|(i, hunk)| format!("\n\n### Hunk {}\n\n{}", i + 1, hunk.prompt_text(excluded)))
+        .collect::<Vec<_>>();
+    let with_limit = |limit| sections.iter().map(|section| limit_file_diffs(section, limit)).collect::<String>();
+    let full = sections.concat();
+    match max_diff_bytes(args)? {
+        Some(max_bytes) if full.len() > max_bytes => {
+            if with_limit(0).len() > max_bytes {
+                return Err(GaiError::Config(format!(
+                    "The {} staged hunks don't fit in {} bytes of diff, even without their changes; raise --max-diff-bytes",
+                    hunks.len(),
+                    max_bytes
+                )).into());
+            }
+            let limit = largest_fitting_limit(full.lines().count(), |limit| with_limit(limit).len() <= max_bytes);
+            content.push_str(&with_limit(limit));
+        }
+        _ => content.push_str(&full),
+    }
+
+    let messages = vec![
+        Message {
+            role: "system".to_string(),
+            content: "You sort changes into conventional commit types. Judge each hunk by what it does, not by the file it is in.".to_string(),
+        },
+        Message {
+            role: "user".to_string(),
+            content,
+        },
+    ];
+
+    let reply = send_chat_request(args, 0.0, messages).await?;
+
+    // Models sometimes wrap JSON in a markdown code fence
+    let json = reply
+        .trim()
+        .trim_start_matches("```json")
+        .trim_start_matches("```")
+        .trim_end_matches("```")
+        .trim();
+
+    let classified = serde_json::from_str::<HunkTypes>(json)
+        .context(GaiError::Provider("Failed to parse hunk classification as JSON".to_string()))?;
+
+    if classified.types.len() != hunks.len() {
+        return Err(GaiError::Provider(format!(
+            "Expected {} hunk types, got {}",
+            hunks.len(),
+            classified.types.len()
+        )).into());
+    }
+
+    // An unexpected type would only produce an oddly grouped commit, so fold it into chore
+    Ok(classified
+        .types
+        .into_iter()
+        .map(|kind| {
+            let kind = kind.trim().to_lowercase();
+            if types.contains(&kind) { kind } else { "chore".to_string() }
+        })
+        .collect())
+}
+
+/// Add `hunks` to the index. A file's rename or mode change only goes in
+/// with the first of its hunks to be staged; `applied` keeps track of the
+/// headers staged so far.
+fn stage_hunks(hunks: &[&Hunk], applied: &mut Vec<String>) -> Result<()> {
+    let mut patch = String::new();
+    let mut header = None;
+    for hunk in hunks {
+        // Hunks of the same file share one header
+        if header != Some(&hunk.header) {
+            if applied.contains(&hunk.header) {
+                patch.push_str(&hunk.plain_header());
+            } else {
+                patch.push_str(&hunk.header);
+                applied.push(hunk.header.clone());
+            }
+            header = Some(&hunk.header);
+        }
+        patch.push_str(&hunk.body);
+    }
+
+    let child = Command::new("git")
+        .args(["apply", "--cached", "--binary", "-"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context(GaiError::Git("Failed to execute git apply command".to_string()))?;
+
+    // git apply may complain about a big patch before it has read all of it
+    let output = communicate(child, patch).context(GaiError::Git("Failed to execute git apply command".to_string()))?;
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(GaiError::Git(format!("git apply failed: {}", error.trim())).into());
+    }
+
+    Ok(())
+}
+
+async fn commit_staged(args: &Args) -> Result<()> {
+    let commit_message = generate_and_record(args).await?;
+
+    create_commit(args, &commit_message)?;
+    add_generation_note(args);
+    println!("✅ Committed with message: \"{}\"", commit_message);
+
+    Ok(())
+}
+
+/// Show a message rated below --min-score and ask before committing it, or
+/// refuse when there's nobody to ask.
+fn confirm_low_score(message: &str, score: u8, min_score: u8) -> Result<()> {
+    let warning = format!("The model rated this message {}/10, below --min-score {}.", score, min_score);
+
+    println!("📝 Generated commit message:");
+    println!("{}", message);
+
+    if !std::io::stdin().is_terminal() {
+        return Err(GaiError::Config(format!("{} Not committing.", warning)).into());
+    }
+
+    let proceed = Confirm::new()
+        .with_prompt(format!("⚠️ {} Commit anyway?", warning))
+        .default(false)
+        .interact()
+        .context(GaiError::Io("Failed to read the confirmation".to_string()))?;
+    if !proceed {
+        return Err(GaiError::Config("Commit cancelled because of the low confidence score".to_string()).into());
+    }
+
+    Ok(())
+}
+
+/// With --confirm-on-large-cost and a terminal to ask on, ask whether to go
+/// on when the run's requests are estimated to cost more than it. Every
+/// candidate or sweep temperature sends `diff` (cut to --max-diff-bytes)
+/// again.
+fn confirm_run_cost(args: &Args, diff: &str) -> Result<()> {
+    let Some(threshold) = args.confirm_on_large_cost else {
+        return Ok(());
+    };
+    if !std::io::stdin().is_terminal() {
+        return Ok(());
+    }
+
+    let sent = match max_diff_bytes(args)? {
+        Some(max_bytes) => diff.len().min(max_bytes),
+        None => diff.len(),
+    };
+    let requests = if args.temperature_sweep.is_empty() { args.candidates } else { args.temperature_sweep.len() };
+    let input_tokens = cost::estimate_tokens(&preset_system_prompt(args)?) + sent.div_ceil(cost::CHARS_PER_TOKEN);
+
+    match cost::cost(&args.model, input_tokens, cost::ESTIMATED_OUTPUT_TOKENS) {
+        Some(estimate) if estimate * requests as f64 > threshold => confirm_cost(estimate * requests as f64),
+        Some(_) => Ok(()),
+        None => {
+            eprintln!("⚠️ No price is known for model '{}', so --confirm-on-large-cost can't estimate it", args.model);
+            Ok(())
+        }
+    }
+}
+
+/// Roughly the diff this run will describe, read without any of the
+/// checks and warnings of the real thing; empty when there's none (yet),
+/// which the action itself reports.
+fn estimated_diff(args: &Args) -> String {
+    if !args.batch.is_empty() {
+        return args.batch.iter().filter_map(|path| fs::read_to_string(path).ok()).collect();
+    }
+    if args.pr {
+        return run_git_diff(args, &[format!("{}...HEAD", args.base)]).unwrap_or_default();
+    }
+    if let Some(command) = &args.diff_command {
+        return run_shell(command, "").unwrap_or_default();
+    }
+
+    let mut diff = run_git_diff(args, &diff_revisions(args)).unwrap_or_default();
+    if diffs_index(args) {
+        diff.push_str(&run_git_diff(args, &[INTENT_TO_ADD_FILTER]).unwrap_or_default());
+    }
+    diff
+}
+
+fn confirm_cost(estimate: f64) -> Result<()> {
+    let proceed = Confirm::new()
+        .with_prompt(format!("💰 This will cost ~${:.2}, continue?", estimate))
+        .default(false)
+        .interact()
+        .context(GaiError::Io("Failed to read the confirmation".to_string()))?;
+    if !proceed {
+        return Err(GaiError::Config(format!("Request cancelled at an estimated ${:.2}", estimate)).into());
+    }
+
+    Ok(())
+}
+
+fn confirm_commit_all(message: &str) -> Result<()> {
+    println!("📝 Generated commit message:");
+    println!("{}", message);
+
+    let proceed = Confirm::new()
+        .with_prompt("Commit all changes with this message?")
+        .default(true)
+        .interact()
+        .context(GaiError::Io("Failed to read the confirmation".to_string()))?;
+    if !proceed {
+        return Err(GaiError::Config("Commit cancelled; the changes are still staged".to_string()).into());
+    }
+
+    Ok(())
+}
+
+/// Fail when a file has both staged and unstaged changes, listing them.
+fn check_partially_staged() -> Result<()> {
+    let unstaged = git(&["diff", "--name-only"])?;
+    let staged = git(&["diff", "--cached", "--name-only"])?;
+    let partial = staged.lines().filter(|file| unstaged.lines().any(|other| other == *file)).collect::<Vec<_>>();
+
+    if partial.is_empty() {
+        return Ok(());
+    }
+
+    Err(GaiError::Git(format!(
+        "These staged files also have unstaged changes, so the commit wouldn't match your working tree:\n{}\nStage or stash the rest first, or drop --require-clean-worktree.",
+        partial.iter().map(|file| format!("  {}", file)).collect::<Vec<_>>().join("\n")
+    )).into())
+}
+
+/// Commits made in detached HEAD state are easy to lose, so ask first, or
+/// refuse when there's nobody to ask.
+fn confirm_detached_head() -> Result<()> {
+    let on_branch = Command::new("git")
+        .args(["symbolic-ref", "-q", "HEAD"])
+        .output()
+        .context(GaiError::Git("Failed to execute git symbolic-ref command".to_string()))?
+        .status
+        .success();
+    if on_branch {
+        return Ok(());
+    }
+
+    let warning = "You are in detached HEAD state; the commit won't be on a branch.";
+    if !std::io::stdin().is_terminal() {
+        return Err(GaiError::Git(format!("{} Use --force to commit anyway.", warning)).into());
+    }
+
+    let proceed = Confirm::new()
+        .with_prompt(format!("⚠️ {} Commit anyway?", warning))
+        .default(false)
+        .interact()
+        .context(GaiError::Io("Failed to read the confirmation".to_string()))?;
+    if !proceed {
+        return Err(GaiError::Git("Commit cancelled because HEAD is detached".to_string()).into());
+    }
+
+    Ok(())
+}
+
+fn create_commit(args: &Args, message: &str) -> Result<()> {
+    stage_intent_to_add(args)?;
+    let root = repository_root()?;
+    let staged = git(&["diff", "--cached", "--name-only"])?;
+    let staged = staged.lines().collect::<Vec<_>>();
+    let before = worktree_hashes(&root, &staged);
+
+    let output = run_git_commit(args, message)?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let error = String::from_utf8_lossy(&output.stderr).trim().to_string();
+
+    let hook = PathBuf::from(git(&["rev-parse", "--git-path", "hooks/pre-commit"])?.trim());
+    if args.no_verify || !hook.is_file() {
+        return Err(GaiError::Git(format!("Commit failed: {}", error)).into());
+    }
+
+    // Formatting hooks usually rewrite the staged files and then fail the
+    // commit; changes that were left unstaged on purpose stay that way
+    let after = worktree_hashes(&root, &staged);
+    let modified = staged
+        .iter()
+        .zip(before.iter().zip(&after))
+        .filter(|(_, (before, after))| before != after)
+        .map(|(file, _)| *file)
+        .collect::<Vec<_>>();
+
+    if !modified.is_empty() && std::io::stdin().is_terminal() {
+        eprintln!("{}", error);
+        let retry = Confirm::new()
+            .with_prompt(format!("⚠️ The pre-commit hook changed {}. Stage its changes and commit again?", modified.join(", ")))
+            .default(true)
+            .interact()
+            .context(GaiError::Io("Failed to read the confirmation".to_string()))?;
+
+        if retry {
+            let root = root.to_string_lossy();
+            let mut add = vec!["-C", &root, "add", "--"];
+            add.extend(&modified);
+            git(&add)?;
+
+            let output = run_git_commit(args, message)?;
+            if output.status.success() {
+                return Ok(());
+            }
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(GaiError::Git(format!("Commit failed again after staging the hook's changes: {}", error.trim())).into());
+        }
+    }
+
+    let mut advice = "The pre-commit hook rejected the commit. Fix what it reports".to_string();
+    if !modified.is_empty() {
+        advice.push_str(&format!(", stage the changes it made to {}", modified.join(", ")));
+    }
+    advice.push_str(" and try again, or use --no-verify to skip the hook.");
+
+    Err(GaiError::Git(format!("Commit failed: {}\n{}", error, advice)).into())
+}
+
+/// With --note, record how HEAD's message was generated in `git notes`.
+/// The commit already exists, so a failure is only a warning.
+fn add_generation_note(args: &Args) {
+    let generated = args.message.is_none() || args.regenerate;
+    if !args.note || !generated {
+        return;
+    }
+
+    let temperature = match args.temperature {
+        Some(temperature) => temperature.to_string(),
+        None if args.temperature_auto => "auto".to_string(),
+        None => DEFAULT_TEMPERATURE.to_string(),
+    };
+    let mut note = format!(
+        "Generated-by: gitai {}\nProvider: {:?}\nModel: {}\nTemperature: {}",
+        env!("CARGO_PKG_VERSION"),
+        args.provider,
+        args.model,
+        temperature
+    );
+    // The patch ID identifies the change itself, whatever the commit's SHA
+    if let Some(id) = git(&["diff-tree", "-p", "--root", "HEAD"]).ok().and_then(|diff| patch_id(&diff)) {
+        note.push_str(&format!("\nPatch-ID: {}", id));
+    }
+
+    if let Err(e) = git(&["notes", "add", "-m", &note, "HEAD"]) {
+        eprintln!("⚠️ Failed to add the generation note: {:#}", e);
+    }
+}
+
+/// The tree of an empty repository, to diff a root commit against.
+const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+/// --rebase-editor: fill in the message file git opens for a `reword` or a
+/// `git commit --amend` at an `edit` stop. Anything else, such as the todo
+/// list, a squash or a commit outside a rebase, goes to the user's own editor.
+async fn edit_rebase_message(args: &mut Args, path: &Path) -> Result<()> {
+    let rebasing = ["rebase-merge", "rebase-apply"]
+        .iter()
+        .any(|dir| git(&["rev-parse", "--git-path", dir]).is_ok_and(|dir| Path::new(dir.trim()).is_dir()));
+    let is_message = path.file_name().is_some_and(|name| name == "COMMIT_EDITMSG");
+    if !rebasing || !is_message {
+        return open_editor(path);
+    }
+
+    // A squash or `fixup -c` message is the user's to put together
+    if let Some(command) = rebase_command()
+        && !["reword", "r", "edit", "e"].contains(&command.as_str())
+    {
+        return open_editor(path);
+    }
+
+    let content = fs::read_to_string(path).with_context(|| GaiError::Io(format!("Failed to read {}", path.display())))?;
+    let comment = comment_prefix();
+    let original = content.lines().filter(|line| !line.starts_with(&comment)).collect::<Vec<_>>().join("\n");
+    let original = original.trim();
+
+    // Amending HEAD shows HEAD's own message; a reword picks a commit that
+    // only exists in the index so far
+    let head = git(&["log", "-1", "--format=%B"]).unwrap_or_default();
+    if !original.is_empty() && head.trim() == original {
+        let parent = git(&["rev-parse", "-q", "--verify", "HEAD^"]).map(|sha| sha.trim().to_string());
+        args.staged_base = Some(parent.unwrap_or_else(|_| EMPTY_TREE.to_string()));
+    }
+
+    let message = match generate_and_record(args).await {
+        Ok(message) => message,
+        Err(e) if e.is::<PromptShown>() => return Err(e),
+        Err(e) => {
+            eprintln!("⚠️ Couldn't generate a message ({:#}); opening your editor instead", e);
+            return open_editor(path);
+        }
+    };
+
+    // Git strips the comment lines, so the old message stays only for reference
+    let reference = original.lines().map(|line| format!("{} {}", comment, line)).collect::<Vec<_>>().join("\n");
+    fs::write(path, format!("{}\n\n{} Original message:\n{}\n", message, comment, reference))
+        .with_context(|| GaiError::Io(format!("Failed to write {}", path.display())))?;
+    if !args.quiet {
+        eprintln!("📝 {}", message.lines().next().unwrap_or_default());
+    }
+
+    Ok(())
+}
+
+/// The todo command an interactive rebase is carrying out, e.g. `reword`:
+/// the last one in its `done` file.
+fn rebase_command() -> Option<String> {
+    let done = git(&["rev-parse", "--git-path", "rebase-merge/done"]).ok()?;
+    let done = fs::read_to_string(done.trim()).ok()?;
+    let line = done.lines().rev().map(str::trim).find(|line| !line.is_empty() && !line.starts_with('#'))?;
+    line.split_whitespace().next().map(str::to_string)
+}
+
+/// Open `path` in $VISUAL or $EDITOR, falling back to vi.
+fn open_editor(path: &Path) -> Result<()> {
+    let editor = env::var("VISUAL").or_else(|_| env::var("EDITOR")).unwrap_or_else(|_| "vi".to_string());
+    let status = shell(&format!("{} {}", editor, shell_quote(&path.to_string_lossy())))
+        .status()
+        .with_context(|| GaiError::Config(format!("Failed to run editor: {}", editor)))?;
+
+    if !status.success() {
+        return Err(GaiError::Config(format!("Editor '{}' exited with {}", editor, status)).into());
+    }
+
+    Ok(())
+}
+
+/// Run `git add --patch` on the user's terminal.
+fn stage_patch() -> Result<()> {
+    ensure_git_repository()?;
+
+    if !std::io::stdin().is_terminal() {
+        return Err(GaiError::Config("--patch needs an interactive terminal".to_string()).into());
+    }
+
+    let status = Command::new("git")
+        .args(["add", "--patch"])
+        .status()
+        .context(GaiError::Git("Failed to execute git add --patch".to_string()))?;
+    if !status.success() {
+        return Err(GaiError::Git("git add --patch failed".to_string()).into());
+    }
+
+    Ok(())
+}
+
+/// The commit where HEAD and its upstream tracking branch diverged, so
+/// commits pulled since don't show up as local changes.
+fn upstream_fork_point() -> Result<String> {
+    ensure_git_repository()?;
+
+    if git(&["rev-parse", "--abbrev-ref", "--symbolic-full-name", "@{u}"]).is_err() {
+        return Err(GaiError::Git(
+            "The current branch has no upstream branch. Set one with 'git branch --set-upstream-to'.".to_string(),
+        ).into());
+    }
+
+    Ok(git(&["merge-base", "@{u}", "HEAD"])?.trim().to_string())
+}
+
+/// Push HEAD to its upstream. The commit already exists, so failures say
+/// so rather than suggesting anything was undone.
+fn push_current_branch() -> Result<()> {
+    let branch = git(&["rev-parse", "--abbrev-ref", "HEAD"])?;
+    let branch = branch.trim();
+
+    if git(&["rev-parse", "--abbrev-ref", "--symbolic-full-name", "@{u}"]).is_err() {
+        let remotes = git(&["remote"]).unwrap_or_default();
+        let remote = remotes.lines().next().unwrap_or("origin");
+        return Err(GaiError::Git(format!(
+            "Committed, but '{}' has no upstream branch, so nothing was pushed. Run 'git push --set-upstream {} {}'.",
+            branch, remote, branch
+        )).into());
+    }
+
+    git(&["push"]).context(GaiError::Git("Committed, but the push failed; the commit is still there".to_string()))?;
+
+    Ok(())
+}
+
+/// A hash of each of `files` (relative to `root`) as it is in the working
+/// tree, `None` when it's missing, to tell which ones a hook rewrote.
+fn worktree_hashes(root: &Path, files: &[&str]) -> Vec<Option<u64>> {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    files
+        .iter()
+        .map(|file| {
+            let content = fs::read(root.join(file)).ok()?;
+            let mut hasher = DefaultHasher::new();
+            content.hash(&mut hasher);
+            Some(hasher.finish())
+        })
+        .collect()
+}
+
+fn run_git_commit(args: &Args, message: &str) -> Result<std::process::Output> {
+    commit_command(args, message)
+        .output()
+        .context(GaiError::Git("Failed to execute git commit command".to_string()))
+}
+
+fn commit_command(args: &Args, message: &str) -> Command {
+    let mut command = Command::new("git");
+    command.args(["commit", "-m", message]);
+    if args.allow_empty {
+        command.arg("--allow-empty");
+    }
+    if args.no_verify {
+        command.arg("--no-verify");
+    }
+    if let Some(date) = &args.date {
+        command.args(["--date", date]);
+    }
+
+    command
+}
+
+fn shell_quote(word: &str) -> String {
+    let safe = !word.is_empty()
+        && word.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=:@%+,".contains(c));
+    if safe {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use providers::Completion;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const API_KEY: &str = "sk-test-0123456789abcdef";
+
+    fn args_for(server: &MockServer) -> Args {
+        Args::parse_from(["gitai", "--base-url", &server.uri()])
+    }
+
+    fn messages() -> Vec<Message> {
+        vec![Message {
+            role: "user".to_string(),
+            content: "diff".to_string(),
+        }]
+    }
+
+    fn reply(content: &str) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({
+            "choices": [{ "message": { "role": "assistant", "content": content } }],
+            "usage": { "prompt_tokens": 12, "completion_tokens": 5 }
+        }))
+    }
+
+    /// Send `messages()` through the OpenAI provider, with `flags` on the
+    /// command line, to a server answering each POST with the next of
+    /// `responses` and with the last one from then on. Returns the result
+    /// along with the requests the server got.
+    async fn exchange(responses: Vec<ResponseTemplate>, flags: &[&str]) -> (Result<Completion>, Vec<wiremock::Request>) {
+        let server = MockServer::start().await;
+        let last = responses.len() - 1;
+        for (i, response) in responses.into_iter().enumerate() {
+            let mock = Mock::given(method("POST")).respond_with(response);
+            let mock = if i < last { mock.up_to_n_times(1) } else { mock };
+            mock.mount(&server).await;
+        }
+
+        let uri = server.uri();
+        let args = Args::parse_from(["gitai", "--base-url", &uri].into_iter().chain(flags.iter().copied()));
+        let result = OPENAI.send(&args, &[API_KEY.to_string()], messages(), Params { temperature: 1.0, response_format: None }).await;
+        (result, server.received_requests().await.unwrap_or_default())
+    }
+
+    fn provider_error(err: &anyhow::Error) -> String {
+        match err.downcast_ref::<GaiError>() {
+            Some(GaiError::Provider(message)) => message.clone(),
+            other => panic!("expected a provider error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn run_shell_streams_a_large_message_through_a_filter() {
+        let message = "feat: add retries\n".repeat(100_000);
+
+        assert_eq!(run_shell("sed 's/retries/backoff/'", &message).unwrap(), message.replace("retries", "backoff"));
+    }
+
+    #[test]
+    fn to_ascii_transliterates_punctuation_and_drops_emoji() {
+        assert_eq!(to_ascii("✨ Add “smart” login — fast…"), "Add \"smart\" login - fast...");
+    }
+
+    #[test]
+    fn apply_subject_case_only_touches_the_description() {
+        let message = "feat(API): Add JWT login\n\nBody Stays";
+
+        assert_eq!(apply_subject_case(message, SubjectCase::Lower), "feat(API): add JWT login\n\nBody Stays");
+        assert_eq!(apply_subject_case("fix: handle x", SubjectCase::Sentence), "fix: Handle x");
+        assert_eq!(apply_subject_case("✨ add login", SubjectCase::Sentence), "✨ Add login");
+        assert_eq!(apply_subject_case(message, SubjectCase::Preserve), message);
+    }
+
+    #[test]
+    fn apply_type_verb_replaces_only_generic_verbs() {
+        assert_eq!(apply_type_verb("fix(ui): fix crash on start\n\nBody", "resolve"), "fix(ui): resolve crash on start\n\nBody");
+        assert_eq!(apply_type_verb("feat: Implement login", "add"), "feat: Add login");
+        assert_eq!(apply_type_verb("fix: remove stale cache", "resolve"), "fix: remove stale cache");
+    }
+
+    #[test]
+    fn diff_words_match_whole_words_only() {
+        let words = diff_words("+let recieve_count = parseTeh(untill);\n-// seperated");
+
+        for word in ["recieve_count", "recieve", "count", "parseteh", "teh", "untill", "seperated"] {
+            assert!(words.contains(word), "missing {}", word);
+        }
+        assert!(!words.contains("seperate"));
+        assert!(!words.contains("recie"));
+    }
+
+    #[test]
+    fn is_test_file_recognizes_test_dirs_and_names() {
+        for path in ["tests/cli.rs", "src/__tests__/app.js", "pkg/foo_test.go", "web/app.spec.ts", "test_utils.py"] {
+            assert!(is_test_file(path), "{}", path);
+        }
+        for path in ["src/main.rs", "src/testing.rs", "contest/entry.py"] {
+            assert!(!is_test_file(path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn strip_emoji_drops_emoji_and_their_spaces() {
+        assert_eq!(strip_emoji("✨ feat: add login 🎉\n\n- 🐛 fix ❤️ the  form"), "feat: add login\n\n- fix the  form");
+    }
+
+    #[test]
+    fn normalize_punctuation_keeps_other_non_ascii() {
+        assert_eq!(normalize_punctuation("fix: “naïve” café — ok…"), "fix: \"naïve\" café - ok...");
+    }
+
+    #[test]
+    fn limit_bullets_drops_extra_bullets_and_their_continuations() {
+        let message = "feat: add retries\n\n- retry on 429\n- retry on 5xx\n  with backoff\n- honor Retry-After";
+
+        assert_eq!(limit_bullets(message, 1), "feat: add retries\n\n- retry on 429");
+        assert_eq!(limit_bullets(message, 3), message);
+    }
+
+    #[test]
+    fn parse_extra_param_types_values() {
+        assert_eq!(parse_extra_param("top_p=0.9"), Ok(("top_p".to_string(), json!(0.9))));
+        assert_eq!(parse_extra_param("seed=7"), Ok(("seed".to_string(), json!(7))));
+        assert_eq!(parse_extra_param("logprobs=true"), Ok(("logprobs".to_string(), json!(true))));
+        assert_eq!(parse_extra_param("stop=\"42\""), Ok(("stop".to_string(), json!("42"))));
+        assert_eq!(parse_extra_param("user=me"), Ok(("user".to_string(), json!("me"))));
+        assert!(parse_extra_param("top_p=0.9.1").is_err());
+        assert!(parse_extra_param("model=gpt-4o").is_err());
+    }
+
+    #[test]
+    fn shell_quote_only_quotes_when_needed() {
+        assert_eq!(shell_quote("--no-verify"), "--no-verify");
+        assert_eq!(shell_quote("fix: don't panic"), "'fix: don'\\''t panic'");
+    }
+
+    #[test]
+    fn redact_tokens_replaces_longer_paths_first() {
+        let tokens = [("src/a".to_string(), "file1".to_string()), ("src/a.rs".to_string(), "file2.rs".to_string())];
+
+        assert_eq!(redact_tokens("Revert \"fix src/a.rs and src/a\"", &tokens), "Revert \"fix file2.rs and file1\"");
+    }
+
+    #[test]
+    fn redact_paths_round_trips_through_the_reply() {
+        let diff = "diff --git a/src/auth/login.rs b/src/auth/login.rs\n--- a/src/auth/login.rs\n+++ b/src/auth/login.rs\n\
+                    @@ -1 +1 @@\n--- a/src/auth/login.rs\n+fixed\n\
+                    diff --git a/my notes.md b/my notes.md\nBinary files a/my notes.md and b/my notes.md differ\n";
+
+        let (redacted, tokens) = redact_paths(diff);
+
+        assert_eq!(
+            redacted,
+            "diff --git a/file1.rs b/file1.rs\n--- a/file1.rs\n+++ b/file1.rs\n\
+             @@ -1 +1 @@\n--- a/src/auth/login.rs\n+fixed\n\
+             diff --git a/file2.md b/file2.md\nBinary files a/file2.md and b/file2.md differ\n"
+        );
+        assert_eq!(
+            restore_tokens("fix(file1): handle file1.rs and file2.md, not file12 or profile1", &tokens),
+            "fix(login): handle src/auth/login.rs and my notes.md, not file12 or profile1"
+        );
+    }
+
+    #[test]
+    fn limit_file_diffs_truncates_each_file_separately() {
+        let diff = "diff --git a/a b/a\n--- a/a\n+++ b/a\n@@ -0,0 +1,3 @@\n+1\n+2\n+3\n\
+                    diff --git a/b b/b\n--- a/b\n+++ b/b\n@@ -0,0 +1 @@\n+1\n";
+
+        assert_eq!(
+            limit_file_diffs(diff, 2),
+            "diff --git a/a b/a\n--- a/a\n+++ b/a\n@@ -0,0 +1,3 @@\n+1\n[truncated: 2 more lines of this file]\n\
+             diff --git a/b b/b\n--- a/b\n+++ b/b\n@@ -0,0 +1 @@\n+1\n"
+        );
+    }
+
+    #[test]
+    fn render_template_fills_in_message_fields() {
+        let message = "feat(api)!: add paging\n\nLists now return pages of {size}.\n\nRefs: #42";
+
+        assert_eq!(
+            render_template("[{type}/{scope}/{breaking}] {description} | {body} | {footers} {unknown}", message),
+            "[feat/api/true] add paging | Lists now return pages of {size}. | Refs: #42 {unknown}"
+        );
+        assert_eq!(render_template("{type}|{description}", "Update readme"), "|Update readme");
+    }
+
+    #[test]
+    fn extract_annotations_takes_notes_out_of_added_lines() {
+        let diff = "+++ b/a.rs\n@@ -1 +1,3 @@\n+// gai: this fixes issue #42\n+let x = 1; // gai: not a note\n-# gai: removed\n+<!-- gai: keep the old API -->\n";
+
+        let (stripped, notes) = extract_annotations(diff);
+
+        assert_eq!(stripped, "+++ b/a.rs\n@@ -1 +1,3 @@\n+let x = 1; // gai: not a note\n-# gai: removed\n");
+        assert_eq!(notes, vec!["this fixes issue #42", "keep the old API"]);
+    }
+
+    #[test]
+    fn conflicted_files_lists_files_with_added_markers() {
+        let diff = "diff --git a/a.rs b/a.rs\n--- a/a.rs\n+++ b/a.rs\n@@ -1 +1,5 @@\n+<<<<<<< HEAD\n+x\n+=======\n+y\n+>>>>>>> topic\n\
+                    diff --git a/b.rst b/b.rst\n--- a/b.rst\n+++ b/b.rst\n@@ -1 +1,2 @@\n+Title\n+=======\n\
+                    diff --git a/c.rs b/c.rs\n--- a/c.rs\n+++ b/c.rs\n@@ -1,2 +1 @@\n-<<<<<<< HEAD\n x\n";
+
+        assert_eq!(conflicted_files(diff), vec!["a.rs"]);
+    }
+
+    #[test]
+    fn diff_changes_reads_status_and_path_from_file_headers() {
+        let diff = "Changed directories:\nsrc/ 2 files, +2/-1\n\n\
+                    diff --git a/src/a.rs b/src/a.rs\nnew file mode 100644\n--- /dev/null\n+++ b/src/a.rs\n@@ -0,0 +1 @@\n+a\n\
+                    diff --git a/src/b.rs b/src/b.rs\ndeleted file mode 100644\n--- a/src/b.rs\n+++ /dev/null\n@@ -1 +0,0 @@\n-b\n\
+                    diff --git a/old.rs b/new.rs\nsimilarity index 100%\nrename from old.rs\nrename to new.rs\n\
+                    diff --git a/logo.png b/logo.png\nBinary files a/logo.png and b/logo.png differ\n";
+
+        let changes = diff_changes(diff);
+
+        assert_eq!(
+            changes,
+            [('A', "src/a.rs"), ('D', "src/b.rs"), ('R', "new.rs"), ('M', "logo.png")].map(|(status, path)| (status, path.to_string()))
+        );
+        assert!(diff_changes("not a diff\n").is_empty());
+    }
+
+    #[test]
+    fn local_message_rules_pick_type_and_description() {
+        let changes = |list: &[(char, &str)]| list.iter().map(|&(status, file)| (status, file.to_string())).collect::<Vec<_>>();
+
+        let docs = changes(&[('M', "README.md"), ('M', "docs/usage.md")]);
+        assert_eq!((local_commit_type(&docs), local_description(&docs).as_str()), ("docs", "update README.md and usage.md"));
+
+        let tests = changes(&[('A', "tests/parser_test.rs")]);
+        assert_eq!((local_commit_type(&tests), local_description(&tests).as_str()), ("test", "add parser_test.rs"));
+
+        let build = changes(&[('M', "Cargo.toml"), ('M', "Cargo.lock")]);
+        assert_eq!(local_commit_type(&build), "build");
+
+        let code = changes(&[('A', "src/a.rs"), ('M', "src/b.rs"), ('M', "src/c.rs"), ('D', "src/d.rs")]);
+        assert_eq!((local_commit_type(&code), local_description(&code).as_str()), ("feat", "update 4 files in src/"));
+    }
+
+    #[test]
+    fn dependency_changes_prefer_lockfile_versions_of_manifest_entries() {
+        let diff = "diff --git a/Cargo.toml b/Cargo.toml\n--- a/Cargo.toml\n+++ b/Cargo.toml\n@@ -9,2 +9,2 @@\n\
+                    -serde = \"1.0\"\n+serde = \"1.1\"\n-tokio = { version = \"1.38\", features = [\"full\"] }\n+tokio = { version = \"1.40\", features = [\"full\"] }\n\
+                    diff --git a/Cargo.lock b/Cargo.lock\n--- a/Cargo.lock\n+++ b/Cargo.lock\n@@ -40,3 +40,3 @@\n name = \"serde\"\n-version = \"1.0.100\"\n+version = \"1.1.0\"\n\
+                    @@ -90,3 +90,3 @@\n name = \"serde_derive\"\n-version = \"1.0.100\"\n+version = \"1.1.0\"\n";
+
+        let changes = dependency_changes(diff).unwrap();
+        assert_eq!(
+            dependency_message_text(&changes),
+            "chore(deps): bump serde and tokio\n\n- bump serde from 1.0.100 to 1.1.0\n- bump tokio from 1.38 to 1.40"
+        );
+
+        let lock_only = &diff[diff.find("diff --git a/Cargo.lock").unwrap()..];
+        assert_eq!(dependency_changes(lock_only).unwrap().len(), 2);
+
+        let release = "diff --git a/Cargo.toml b/Cargo.toml\n@@ -3 +3 @@\n-version = \"0.1.0\"\n+version = \"0.2.0\"\n";
+        assert_eq!(dependency_changes(release), None);
+
+        let code = "diff --git a/src/main.rs b/src/main.rs\n@@ -1 +1 @@\n-a\n+b\n";
+        assert_eq!(dependency_changes(code), None);
+    }
+
+    #[test]
+    fn directory_summary_groups_by_top_level_directory() {
+        let numstat = "1\t1\tREADME.md\n10\t2\tsrc/main.rs\n5\t0\tsrc/lint.rs\n-\t-\tsrc/logo.png\n";
+
+        assert_eq!(
+            directory_summary(numstat).unwrap(),
+            "Changed directories:\nsrc/ 3 files, +15/-2\n(root) 1 file, +1/-1\n"
+        );
+        assert_eq!(directory_summary("1\t1\tsrc/main.rs\n"), None);
+    }
+
+    #[test]
+    fn ci_annotation_escapes_newlines_and_percent() {
+        assert_eq!(
+            ci_annotation("Git error", "100% broken:\nretry"),
+            "::error title=gitai Git error::100%25 broken:%0Aretry"
+        );
+    }
+
+    #[test]
+    fn commit_message_text_drops_comments_and_the_verbose_diff() {
+        let raw = "feat: add login\n\nBody\n# Please enter the commit message\n# ------------------------ >8 ------------------------\ndiff --git a/a b/a\n";
+        assert_eq!(commit_message_text(raw, "#"), "feat: add login\n\nBody");
+
+        let raw = "feat: add login\n\n#42 is fixed\n; Please enter the commit message\n; ------------------------ >8 ------------------------\ndiff --git a/a b/a\n";
+        assert_eq!(commit_message_text(raw, ";"), "feat: add login\n\n#42 is fixed");
+    }
+
+    #[test]
+    fn has_visible_changes_ignores_blank_lines() {
+        assert!(!has_visible_changes("--- a/a.rs\n+++ b/a.rs\n@@ -1,3 +1,4 @@\n fn a() {\n+\n-   \n"));
+        assert!(has_visible_changes("--- a/a.rs\n+++ b/a.rs\n@@ -1 +1 @@\n-x\n+y\n"));
+    }
+
+    #[test]
+    fn truncate_diff_keeps_whole_lines() {
+        let diff = "diff --git a/a b/a\n+one\n+two\n";
+
+        let truncated = truncate_diff(diff, 25).unwrap();
+        assert_eq!(truncated, Truncated { kept: "diff --git a/a b/a\n+one\n", dropped_lines: 1 });
+        assert_eq!(truncated.to_string(), "diff --git a/a b/a\n+one\n[truncated: 1 more lines of the diff]\n");
+        assert_eq!(truncate_diff(diff, 10), Some(Truncated { kept: "", dropped_lines: 3 }));
+        assert_eq!(truncate_diff(diff, 100), None);
+    }
+
+    #[test]
+    fn condense_diff_shares_the_budget_between_files() {
+        let big = (0..100).map(|i| format!("+line {}\n", i)).collect::<String>();
+        let diff = format!(
+            "diff --git a/big b/big\n@@ -0,0 +1,100 @@\n{}diff --git a/small b/small\n@@ -1 +1 @@\n-a\n+b\ndiff --git a/logo.png b/logo.png\nBinary files a/logo.png and b/logo.png differ\n",
+            big
+        );
+        let files = file_list("100\t0\tbig\n1\t1\tsmall\n-\t-\tlogo.png\n");
+        let condensed = condense_diff(&diff, &files, 400).unwrap();
+
+        assert!(condensed.len() <= 400);
+        assert!(condensed.starts_with("Changed files:\nbig (+100 -0)\nsmall (+1 -1)\nlogo.png (binary)\n"));
+        assert!(condensed.contains("+line 0\n") && !condensed.contains("+line 99\n"));
+        assert!(condensed.contains("-a\n+b\n"));
+        assert!(condensed.contains("Binary files a/logo.png and b/logo.png differ"));
+        assert_eq!(condense_diff(&diff, &files, 10), None);
+    }
+
+    #[test]
+    fn compact_diff_collapses_runs_of_context() {
+        let diff = "diff --git a/a b/a\n--- a/a\n+++ b/a\n@@ -1,6 +1,6 @@ fn main\n a\n b\n-c\n+C\n d\n+e\n f\n g\n h\n";
+
+        assert_eq!(
+            compact_diff(diff),
+            "diff --git a/a b/a\n--- a/a\n+++ b/a\n@@ -1,6 +1,6 @@ fn main\n [2 unchanged lines]\n-c\n+C\n d\n+e\n [3 unchanged lines]\n"
+        );
+    }
+
+    #[test]
+    fn split_hunks_keeps_the_file_header_with_each_hunk() {
+        let diff = "diff --git a/f b/f\nindex 1..2 100644\n--- a/f\n+++ b/f\n@@ -1 +1 @@\n-1\n+one\n@@ -9 +9 @@\n-9\n+nine\n\
+                    diff --git a/g.png b/g.png\nBinary files differ\n";
+
+        let hunks = split_hunks(diff);
+
+        assert_eq!(hunks.len(), 3);
+        assert_eq!(hunks[0].header, "diff --git a/f b/f\nindex 1..2 100644\n--- a/f\n+++ b/f\n");
+        assert_eq!(hunks[0].body, "@@ -1 +1 @@\n-1\n+one\n");
+        assert_eq!(hunks[1].header, hunks[0].header);
+        assert_eq!(hunks[1].body, "@@ -9 +9 @@\n-9\n+nine\n");
+        assert_eq!(hunks[2].header, "diff --git a/g.png b/g.png\nBinary files differ\n");
+        assert!(hunks[2].body.is_empty());
+    }
+
+    #[test]
+    fn stage_intent_to_add_skips_files_outside_the_diff() {
+        let repo = std::env::temp_dir().join(format!("gitai-ita-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&repo);
+        fs::create_dir_all(&repo).unwrap();
+        let run = |args: &[&str]| assert!(Command::new("git").args(args).current_dir(&repo).output().unwrap().status.success());
+        run(&["init", "-q"]);
+        fs::write(repo.join("lib.rs"), "fn a() {}\n").unwrap();
+        fs::write(repo.join("notes.md"), "# Notes\n").unwrap();
+        run(&["add", "-N", "lib.rs", "notes.md"]);
+
+        let previous = env::current_dir().unwrap();
+        env::set_current_dir(&repo).unwrap();
+        let staged = stage_intent_to_add(&Args::parse_from(["gitai", "--include-ext", "rs"]))
+            .and_then(|_| git(&["diff", "--cached", "--name-only", "--diff-filter=A"]));
+        let still_intent_to_add = git(&["diff", "--name-only", INTENT_TO_ADD_FILTER]);
+        env::set_current_dir(previous).unwrap();
+        let _ = fs::remove_dir_all(&repo);
+
+        assert_eq!(staged.unwrap(), "lib.rs\n");
+        assert_eq!(still_intent_to_add.unwrap(), "notes.md\n");
+    }
+
+    #[test]
+    fn max_diff_bytes_zero_means_no_limit() {
+        let mut args = Args::parse_from(["gitai", "--max-diff-bytes", "0"]);
+        assert_eq!(max_diff_bytes(&args).unwrap(), None);
+
+        args.max_diff_bytes = Some(500);
+        assert_eq!(max_diff_bytes(&args).unwrap(), Some(500));
+    }
+
+    #[test]
+    fn every_action_flag_counts_as_an_action() {
+        let actions: &[&[&str]] = &[
+            &["history"],
+            &["--generate"],
+            &["--commit"],
+            &["--commit-all"],
+            &["--explain"],
+            &["--pr"],
+            &["--pr-url", "https://github.com/owner/repo/pull/1"],
+            &["--interactive"],
+            &["--auto-split"],
+            &["--split-by-package"],
+            &["--watch"],
+            &["--prewarm"],
+            &["--fix-scope", "--message", "feat: x"],
+            &["--fix-type", "--message", "feat: x"],
+            &["--capabilities"],
+            &["--record-commit"],
+            &["--install-hook"],
+            &["--validate-only"],
+            &["--verify-model"],
+            &["--batch", "a.diff"],
+            &["--temperature-sweep", "0.2,0.7"],
+        ];
+
+        assert!(!Args::parse_from(["gitai"]).has_action());
+        for flags in actions {
+            let args = Args::try_parse_from(std::iter::once("gitai").chain(flags.iter().copied())).unwrap();
+            assert!(args.has_action(), "{:?} is not an action", flags);
+        }
+    }
+
+    #[test]
+    fn glob_matches_like_a_pathspec() {
+        assert!(glob_matches("**/.env", ".env"));
+        assert!(glob_matches("**/.env", "config/.env"));
+        assert!(glob_matches("secrets/*.pem", "secrets/k.pem"));
+        assert!(!glob_matches("secrets/*.pem", "secrets/old/k.pem"));
+        assert!(glob_matches("secrets/**", "secrets/old/k.pem"));
+        assert!(glob_matches("secrets", "secrets/old/k.pem"));
+        assert!(!glob_matches("secrets", "secretsfile"));
+        assert!(glob_matches("key?.txt", "key1.txt"));
+    }
+
+    #[test]
+    fn filter_do_not_send_drops_matching_files_in_exclude_mode() {
+        let mut args = Args::parse_from(["gitai"]);
+        args.config.do_not_send = Some(vec![".env".to_string()]);
+        let diff = "diff --git a/.env b/.env\n--- a/.env\n+++ b/.env\n@@ -1 +1 @@\n-A=1\n+A=2\n\
+                    diff --git a/src/f.rs b/src/f.rs\n--- a/src/f.rs\n+++ b/src/f.rs\n@@ -1 +1 @@\n-1\n+2\n";
+
+        assert!(filter_do_not_send(&args, diff).is_err());
+
+        args.config.do_not_send_mode = Some(DoNotSendMode::Exclude);
+        let filtered = filter_do_not_send(&args, diff).unwrap();
+        assert!(filtered.starts_with("diff --git a/src/f.rs"));
+        assert!(!filtered.contains("A=2"));
+    }
+
+    fn plugin_args(plugin: &str) -> Args {
+        let mut args = Args::parse_from(["gitai"]);
+        args.config.plugins = Some(vec![plugin.to_string()]);
+        args
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn plugins_get_the_message_and_the_diff_sent_as_json() {
+        let input = std::env::temp_dir().join(format!("gitai-plugin-{}.json", std::process::id()));
+        let args = plugin_args(&format!("cat > '{}'", input.display()));
+
+        let message = run_plugins(&args, "feat: add retries".to_string(), Some("diff --git a/f b/f\n")).unwrap();
+
+        let sent = serde_json::from_str::<Value>(&fs::read_to_string(&input).unwrap()).unwrap();
+        let _ = fs::remove_file(&input);
+        assert_eq!(message, "feat: add retries");
+        assert_eq!(
+            sent,
+            json!({
+                "version": PLUGIN_API_VERSION,
+                "message": "feat: add retries",
+                "diff": "diff --git a/f b/f\n",
+                "provider": "openai",
+                "model": args.model,
+                "generated": true,
+            })
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn plugins_replace_veto_or_fail_by_exit_code() {
+        let run = |plugin: &str| run_plugins(&plugin_args(plugin), "feat: add retries".to_string(), Some("diff --git a/f b/f\n"));
+
+        assert_eq!(run("cat > /dev/null; echo 'fix: handle retries'").unwrap(), "fix: handle retries");
+        assert_eq!(run("cat > /dev/null").unwrap(), "feat: add retries");
+
+        let veto = run("cat > /dev/null; echo 'no ticket number'; exit 1").unwrap_err();
+        assert!(format!("{:#}", veto).contains("vetoed the message: no ticket number"));
+
+        let failure = run("exit 3").unwrap_err();
+        assert!(format!("{:#}", failure).contains("failed"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn plugins_may_print_a_lot_before_reading_their_input() {
+        // Both pipes fill up unless the input is written while the output is read
+        let plugin = "head -c 1000000 /dev/zero | tr '\\0' x >&2; cat > /dev/null";
+        let diff = "+".repeat(1_000_000);
+
+        let message = run_plugins(&plugin_args(plugin), "feat: add retries".to_string(), Some(&diff)).unwrap();
+
+        assert_eq!(message, "feat: add retries");
+    }
+
+    #[test]
+    fn command_diff_reads_a_diff_of_any_size() {
+        let command = "cat > /dev/null; printf 'diff --git a/a b/a\\n'; head -c 500000 /dev/zero | tr '\\0' x";
+        let diff = command_diff(&Args::parse_from(["gitai"]), command).unwrap();
+
+        assert!(diff.starts_with("diff --git a/a b/a\nxxx"));
+        assert_eq!(diff.len(), "diff --git a/a b/a\n".len() + 500_000);
+    }
+
+    #[test]
+    fn later_hunks_of_a_renamed_file_name_only_the_new_path() {
+        let diff = "diff --git a/old.rs b/new.rs\nsimilarity index 90%\nrename from old.rs\nrename to new.rs\n--- a/old.rs\n+++ b/new.rs\n\
+                    @@ -1 +1 @@\n-1\n+one\n@@ -9 +9 @@\n-9\n+nine\n\
+                    diff --git a/g.png b/g.png\nindex 1..2 100644\nGIT binary patch\nliteral 3\nKcmZ?wbn^m^\n";
+
+        let hunks = split_hunks(diff);
+
+        assert_eq!(hunks[1].path(), "new.rs");
+        assert_eq!(hunks[1].plain_header(), "diff --git a/new.rs b/new.rs\n--- a/new.rs\n+++ b/new.rs\n");
+        assert_eq!(hunks[2].path(), "g.png");
+        assert_eq!(hunks[2].prompt_text(&[]), "diff --git a/g.png b/g.png\nindex 1..2 100644\nBinary files differ\n");
+        assert!(hunks[0].prompt_text(&["new.rs".to_string()]).ends_with("+++ b/new.rs\n[changes not sent (do_not_send)]\n"));
+    }
+
+    #[tokio::test]
+    async fn returns_the_first_choice_and_usage() {
+        let (completion, requests) = exchange(vec![reply("feat: add retries")], &[]).await;
+
+        let completion = completion.unwrap();
+        assert_eq!(completion.text, "feat: add retries");
+        let usage = completion.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (12, 5));
+        assert_eq!(requests[0].url.path(), "/chat/completions");
+        assert_eq!(requests[0].headers["authorization"], format!("Bearer {}", API_KEY).as_str());
+    }
+
+    #[tokio::test]
+    async fn reads_output_text_from_the_responses_api() {
+        let response = ResponseTemplate::new(200).set_body_json(json!({
+            "output": [
+                { "type": "reasoning", "summary": [] },
+                { "type": "message", "content": [{ "type": "output_text", "text": "fix: handle timeouts" }] }
+            ],
+            "usage": { "input_tokens": 20, "output_tokens": 4 }
+        }));
+        let (completion, requests) = exchange(vec![response], &["--api", "responses"]).await;
+
+        let completion = completion.unwrap();
+        assert_eq!(completion.text, "fix: handle timeouts");
+        let usage = completion.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (20, 4));
+        assert_eq!(requests[0].url.path(), "/responses");
+    }
+
+    #[tokio::test]
+    async fn retries_after_rate_limit() {
+        let rate_limited = ResponseTemplate::new(429).insert_header("Retry-After", "0");
+        let (completion, requests) = exchange(vec![rate_limited, reply("fix: handle rate limits")], &[]).await;
+
+        assert_eq!(completion.unwrap().text, "fix: handle rate limits");
+        assert_eq!(requests.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn fails_over_to_the_next_key_when_rate_limited() {
+        let server = MockServer::start().await;
+        let other_key = "sk-test-fedcba9876543210";
+        Mock::given(header("Authorization", format!("Bearer {}", API_KEY).as_str()))
+            .respond_with(ResponseTemplate::new(429))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(header("Authorization", format!("Bearer {}", other_key).as_str()))
+            .respond_with(reply("fix: spread requests over keys"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let keys = [API_KEY.to_string(), other_key.to_string()];
+        let completion = OPENAI
+            .send(&args_for(&server), &keys, messages(), Params { temperature: 1.0, response_format: None })
+            .await
+            .unwrap();
+
+        assert_eq!(completion.text, "fix: spread requests over keys");
+    }
+
+    #[tokio::test]
+    async fn ollama_sends_no_key_and_reads_its_own_reply_shape() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .and(|request: &wiremock::Request| !request.headers.contains_key("authorization"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "model": "llama3.2",
+                "message": { "role": "assistant", "content": "feat: run models locally" },
+                "done": true,
+                "prompt_eval_count": 30,
+                "eval_count": 6
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let args = Args::parse_from(["gitai", "--provider", "ollama", "--base-url", &server.uri()]);
+        let completion = Ollama.complete(&args, messages(), Params { temperature: 1.0, response_format: None }).await.unwrap();
+
+        assert_eq!(completion.text, "feat: run models locally");
+        assert_eq!(completion.usage.map(|usage| (usage.prompt_tokens, usage.completion_tokens)), Some((30, 6)));
+    }
+
+    #[tokio::test]
+    async fn retries_an_attempt_that_times_out() {
+        let too_late = reply("feat: too late").set_delay(Duration::from_secs(3));
+        let (completion, requests) = exchange(vec![too_late, reply("fix: answer in time")], &["--attempt-timeout", "1"]).await;
+
+        assert_eq!(completion.unwrap().text, "fix: answer in time");
+        assert_eq!(requests.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_last_retry() {
+        let unavailable = ResponseTemplate::new(503).insert_header("Retry-After", "0");
+        let (completion, requests) = exchange(vec![unavailable], &["--retries", "1"]).await;
+
+        assert!(provider_error(&completion.unwrap_err()).contains("503"));
+        assert_eq!(requests.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_reply_without_choices() {
+        let (completion, _) = exchange(vec![ResponseTemplate::new(200).set_body_json(json!({ "choices": [] }))], &[]).await;
+
+        assert_eq!(provider_error(&completion.unwrap_err()), "No choices in response");
+    }
+
+    #[tokio::test]
+    async fn surfaces_the_api_error_message() {
+        let unauthorized = ResponseTemplate::new(401).set_body_json(json!({ "error": { "message": "Incorrect API key provided" } }));
+        let (completion, _) = exchange(vec![unauthorized], &[]).await;
+
+        assert_eq!(
+            provider_error(&completion.unwrap_err()),
+            "OpenAI API request failed (401 Unauthorized): Incorrect API key provided"
+        );
+    }
+
+    #[tokio::test]
+    async fn suggests_close_models_when_verifying_an_unknown_one() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/models"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "data": [{ "id": "o3" }, { "id": "gpt-4.1" }, { "id": "gpt-4.1-mini" }, { "id": "gpt-4o" }]
+            })))
+            .mount(&server)
+            .await;
+
+        let mut args = args_for(&server);
+        args.model = "gpt-4.9".to_string();
+        let err = OPENAI.check_model_listed(&args, API_KEY).await.unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Model 'gpt-4.9' not found for the OpenAI provider; did you mean 'gpt-4.1', 'gpt-4o', 'gpt-4.1-mini'?"
+        );
+
+        args.model = "gpt-4o".to_string();
+        OPENAI.check_model_listed(&args, API_KEY).await.unwrap();
+    }
 }
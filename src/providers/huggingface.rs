@@ -0,0 +1,91 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{Completion, Params, Provider};
+use crate::error::GaiError;
+use crate::{Args, Message};
+
+const BASE_URL: &str = "https://api-inference.huggingface.co";
+
+/// The Hugging Face Inference API for text-generation models.
+pub struct HuggingFace;
+
+#[derive(Serialize, Debug)]
+struct HuggingFaceRequest {
+    inputs: String,
+    parameters: HuggingFaceParameters,
+}
+
+#[derive(Serialize, Debug)]
+struct HuggingFaceParameters {
+    temperature: f32,
+    max_new_tokens: u32,
+    return_full_text: bool,
+    #[serde(flatten)]
+    extra: serde_json::Map<String, Value>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum HuggingFaceResponse {
+    Generations(Vec<HuggingFaceGeneration>),
+    Error { error: String },
+}
+
+#[derive(Deserialize, Debug)]
+struct HuggingFaceGeneration {
+    generated_text: String,
+}
+
+impl Provider for HuggingFace {
+    async fn complete(&self, args: &Args, messages: Vec<Message>, params: Params) -> Result<Completion> {
+        let api_key = super::read_api_key("HF_API_TOKEN")?;
+        let client = crate::http_client()?;
+
+        // Text-generation models take a single prompt instead of chat messages
+        let inputs = messages
+            .iter()
+            .map(|m| m.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        let request = HuggingFaceRequest {
+            inputs,
+            parameters: HuggingFaceParameters {
+                // The Inference API rejects a temperature of exactly zero
+                temperature: params.temperature.max(0.01),
+                max_new_tokens: 256,
+                return_full_text: false,
+                extra: args.extra_params.iter().cloned().collect(),
+            },
+        };
+
+        let base_url = args.base_url.as_deref().unwrap_or(BASE_URL).trim_end_matches('/');
+        let url = format!("{}/models/{}", base_url, args.model);
+        let builder = super::with_headers(client.post(url), args, &api_key)?.json(&request);
+        let response = super::send_with_retries(args, builder, "Hugging Face Inference API").await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = super::read_body(args, response).await.unwrap_or_else(|_| "Unknown error".to_string());
+            return Err(super::provider_failure(args, format!("API request failed: {}", error_text), status, &error_text));
+        }
+
+        let body = super::read_body(args, response).await?;
+        let response_body = serde_json::from_str::<HuggingFaceResponse>(&body)
+            .context(GaiError::Provider("Failed to parse Hugging Face API response".to_string()))?;
+
+        match response_body {
+            HuggingFaceResponse::Generations(generations) => Ok(Completion {
+                text: generations
+                    .into_iter()
+                    .next()
+                    .context(GaiError::Provider("No generations in response".to_string()))?
+                    .generated_text,
+                usage: None,
+            }),
+            HuggingFaceResponse::Error { error } => Err(GaiError::Provider(format!("Hugging Face API error: {}", error)).into()),
+        }
+    }
+}
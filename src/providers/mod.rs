@@ -0,0 +1,305 @@
+use anyhow::{Context, Result};
+use reqwest::header::RETRY_AFTER;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::error::GaiError;
+use crate::{Args, Message};
+
+mod huggingface;
+mod ollama;
+mod openai;
+mod vertex;
+
+pub use huggingface::HuggingFace;
+pub use ollama::Ollama;
+pub use openai::{DEEPSEEK, MISTRAL, OPENAI, OpenAICompatible};
+pub use vertex::Vertex;
+
+/// The first backoff delay; each retry doubles it.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// API calls made so far in this invocation, for --retry-budget.
+static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+/// Index of the key that last got through, so later requests start with it.
+static ACTIVE_KEY: AtomicUsize = AtomicUsize::new(0);
+
+/// A chat completion API that gitai can send prompts to.
+pub trait Provider {
+    /// Send `messages` and return the model's reply.
+    async fn complete(&self, args: &Args, messages: Vec<Message>, params: Params) -> Result<Completion>;
+}
+
+/// Per-request settings that vary between calls with the same arguments.
+#[derive(Debug)]
+pub struct Params {
+    pub temperature: f32,
+    /// A JSON schema for a structured reply, for providers that support one
+    pub response_format: Option<Value>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+}
+
+/// A provider's reply along with its token usage, when reported.
+#[derive(Debug)]
+pub struct Completion {
+    pub text: String,
+    pub usage: Option<Usage>,
+}
+
+/// Send a request, retrying rate limits, server errors and timed out
+/// attempts up to --retries times, all within --total-timeout.
+pub async fn send_with_retries(args: &Args, builder: RequestBuilder, service: &str) -> Result<Response> {
+    within_total_timeout(args, service, send_attempts(args, builder, service, true)).await
+}
+
+/// Send a request signed with each of `keys` in turn, starting from the one
+/// that last worked, until one isn't rate limited. Only the last key waits
+/// out rate limits with --retries.
+pub async fn send_with_failover<F>(args: &Args, keys: &[String], service: &str, build: F) -> Result<Response>
+where
+    F: Fn(&str) -> Result<RequestBuilder>,
+{
+    let attempts = async {
+        let start = ACTIVE_KEY.load(Ordering::Relaxed);
+        for tried in 1..=keys.len() {
+            let index = (start + tried - 1) % keys.len();
+            let last = tried == keys.len();
+            let response = send_attempts(args, build(&keys[index])?, service, last).await?;
+
+            if last || response.status() != StatusCode::TOO_MANY_REQUESTS {
+                ACTIVE_KEY.store(index, Ordering::Relaxed);
+                return Ok(response);
+            }
+            eprintln!("⏳ {} rate limited key {} of {}; trying the next one", service, index + 1, keys.len());
+        }
+        Err(GaiError::Config("No API keys to send the request with".to_string()).into())
+    };
+
+    within_total_timeout(args, service, attempts).await
+}
+
+async fn within_total_timeout(args: &Args, service: &str, attempts: impl Future<Output = Result<Response>>) -> Result<Response> {
+    match args.total_timeout {
+        Some(secs) => tokio::time::timeout(Duration::from_secs(secs), attempts).await.unwrap_or_else(|_| {
+            Err(GaiError::Provider(format!("The {} didn't finish within --total-timeout {}s", service, secs)).into())
+        }),
+        None => attempts.await,
+    }
+}
+
+/// Honors `Retry-After` in seconds between attempts, otherwise backs off
+/// exponentially. Rate limits are returned at once unless `retry_rate_limits`.
+async fn send_attempts(args: &Args, builder: RequestBuilder, service: &str, retry_rate_limits: bool) -> Result<Response> {
+    let mut attempt = 0;
+    loop {
+        if let Some(budget) = args.retry_budget
+            && CALLS.fetch_add(1, Ordering::Relaxed) >= budget
+        {
+            return Err(GaiError::Config(format!(
+                "Stopped before calling the {}: all {} API calls allowed by --retry-budget are used up",
+                service, budget
+            )).into());
+        }
+
+        let mut request = builder
+            .try_clone()
+            .context(GaiError::Provider("Failed to prepare the request for sending".to_string()))?;
+        if let Some(secs) = args.attempt_timeout {
+            request = request.timeout(Duration::from_secs(secs));
+        }
+
+        let response = match request.send().await {
+            Ok(response) => response,
+            // A connect timeout means the network is down, which a retry won't fix
+            Err(e) if e.is_timeout() && !e.is_connect() && attempt < args.retries => {
+                eprintln!("⏳ {} didn't answer in time; retrying", service);
+                attempt += 1;
+                continue;
+            }
+            Err(e) => return Err(crate::request_error(e, service)),
+        };
+
+        let status = response.status();
+        let retryable = (status == StatusCode::TOO_MANY_REQUESTS && retry_rate_limits) || status.is_server_error();
+        if !retryable || attempt == args.retries {
+            return Ok(response);
+        }
+
+        let delay = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok()?.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(RETRY_DELAY * 2u32.pow(attempt));
+        eprintln!("⏳ {} returned {}; retrying in {:.1}s", service, status, delay.as_secs_f32());
+
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// Read an API key from the environment, rejecting blank values and warning
+/// about truncated ones that would otherwise surface as a confusing 401.
+pub fn read_api_key(var: &str) -> Result<String> {
+    let key = env::var(var)
+        .with_context(|| GaiError::Config(format!("{} not found. Please set it in your .env file or environment variables.", var)))?;
+    check_api_key(var, &key)
+}
+
+/// Read the comma separated keys in `<var>S`, e.g. OPENAI_API_KEYS, falling
+/// back to the single key in `var`.
+pub fn read_api_keys(var: &str) -> Result<Vec<String>> {
+    let list_var = format!("{}S", var);
+    let Ok(list) = env::var(&list_var) else {
+        return Ok(vec![read_api_key(var)?]);
+    };
+
+    let keys = list
+        .split(',')
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .map(|key| check_api_key(&list_var, key))
+        .collect::<Result<Vec<_>>>()?;
+    if keys.is_empty() {
+        return Err(GaiError::Config(format!("{} is empty. Please set it in your .env file or environment variables.", list_var)).into());
+    }
+
+    Ok(keys)
+}
+
+fn check_api_key(var: &str, key: &str) -> Result<String> {
+    let key = key.trim();
+
+    if key.is_empty() {
+        return Err(GaiError::Config(format!("{} is empty. Please set it in your .env file or environment variables.", var)).into());
+    }
+
+    // Hosted providers' keys are longer, but a proxy in front of one may use any format
+    if key.len() < 20 {
+        eprintln!("⚠️ {} is shorter than API keys usually are; if requests fail with 401, check your .env file for a truncated value.", var);
+    }
+
+    Ok(key.to_string())
+}
+
+/// Add the JSON content type, bearer auth and any user supplied headers.
+pub fn with_headers(builder: RequestBuilder, args: &Args, api_key: &str) -> Result<RequestBuilder> {
+    with_optional_auth(builder, args, Some(api_key))
+}
+
+/// Like `with_headers`, leaving out Authorization when there's no key.
+pub fn with_optional_auth(mut builder: RequestBuilder, args: &Args, api_key: Option<&str>) -> Result<RequestBuilder> {
+    // Custom headers may only replace Authorization when explicitly allowed
+    let overrides_auth = args.headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("authorization"));
+    if overrides_auth && !args.allow_auth_override {
+        return Err(GaiError::Config("Refusing to override the Authorization header. Pass --allow-auth-override to allow it.".to_string()).into());
+    }
+
+    builder = builder.header("Content-Type", "application/json");
+
+    if !overrides_auth && let Some(api_key) = api_key {
+        builder = builder.header("Authorization", format!("Bearer {}", api_key));
+    }
+
+    for (name, value) in &args.headers {
+        builder = builder.header(name, value);
+    }
+
+    Ok(builder)
+}
+
+/// Read a provider response body, appending it to the --dump-response file
+/// when one was given.
+pub async fn read_body(args: &Args, response: Response) -> Result<String> {
+    let body = response
+        .text()
+        .await
+        .context(GaiError::Provider("Failed to read the provider response".to_string()))?;
+
+    if let Some(path) = &args.dump_response {
+        let written = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", body.trim_end()));
+        // Losing the dump shouldn't cost the message
+        if let Err(e) = written {
+            eprintln!("⚠️ Failed to write {}: {}", path.display(), e);
+        }
+    }
+
+    Ok(body)
+}
+
+/// A provider error, followed by a plain-language explanation with
+/// --explain-failure when the failure is a familiar one.
+pub fn provider_failure(args: &Args, message: String, status: StatusCode, body: &str) -> anyhow::Error {
+    match explain_failure(args, status, body) {
+        Some(explanation) if args.explain_failure => GaiError::Provider(format!("{}\n💡 {}", message, explanation)).into(),
+        _ => GaiError::Provider(message).into(),
+    }
+}
+
+/// Recognize the usual causes of a failed request from the status and the
+/// wording providers use for them.
+fn explain_failure(args: &Args, status: StatusCode, body: &str) -> Option<String> {
+    let body = body.to_lowercase();
+    let mentions = |words: &[&str]| words.iter().any(|word| body.contains(word));
+
+    let credentials = match args.provider.openai_compatible() {
+        Some(endpoint) => endpoint.api_key_var,
+        None if args.provider == crate::Provider::Vertex => "GOOGLE_APPLICATION_CREDENTIALS",
+        None if args.provider == crate::Provider::Ollama => "OLLAMA_API_KEY",
+        None => "HF_API_TOKEN",
+    };
+
+    let explanation = if mentions(&["insufficient_quota", "quota", "billing", "credit"]) {
+        "Your account has run out of quota or credit. Check the billing page of your provider account, or switch to another provider.".to_string()
+    } else if status == StatusCode::UNAUTHORIZED || mentions(&["invalid api key", "incorrect api key", "invalid_api_key", "unauthenticated"]) {
+        format!("The provider rejected your credentials. Check that {} holds a current key for the {:?} provider.", credentials, args.provider)
+    } else if status == StatusCode::NOT_FOUND || mentions(&["model_not_found", "does not exist", "unknown model", "invalid model"]) {
+        format!("The model '{}' isn't available to you. Run with --verify-model to see similar models, or pick another with --model.", args.model)
+    } else if mentions(&["context_length_exceeded", "maximum context length", "too many tokens", "too long"]) {
+        "The diff is too large for this model. Stage fewer changes, limit it with --diff-file-limit or --include-ext, or use a model with a larger context.".to_string()
+    } else if status == StatusCode::TOO_MANY_REQUESTS {
+        "You're sending requests too quickly. Wait a moment, lower --concurrency or raise --retries.".to_string()
+    } else if status == StatusCode::FORBIDDEN {
+        "Your key isn't allowed to use this model or project. Check the key's permissions and --openai-org / --openai-project.".to_string()
+    } else if status.is_server_error() {
+        "The provider is having problems on its side. Try again later or switch providers.".to_string()
+    } else {
+        return None;
+    };
+
+    Some(explanation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn explain_failure_recognizes_common_errors() {
+        let args = Args::parse_from(["gitai", "--model", "gpt-9"]);
+        let explain = |status, body| explain_failure(&args, status, body).unwrap_or_default();
+
+        assert!(explain(StatusCode::TOO_MANY_REQUESTS, "You exceeded your current quota").contains("out of quota"));
+        assert!(explain(StatusCode::TOO_MANY_REQUESTS, "Rate limit reached").contains("too quickly"));
+        assert!(explain(StatusCode::UNAUTHORIZED, "Incorrect API key provided").contains("OPENAI_API_KEY"));
+        assert!(explain(StatusCode::NOT_FOUND, "The model `gpt-9` does not exist").contains("'gpt-9'"));
+        assert_eq!(explain_failure(&args, StatusCode::BAD_REQUEST, "Unsupported parameter: 'top_k'"), None);
+    }
+}
@@ -0,0 +1,90 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::env;
+
+use super::{Completion, Params, Provider, Usage};
+use crate::error::GaiError;
+use crate::{Args, Message};
+
+const BASE_URL: &str = "http://localhost:11434";
+
+/// A local or self-hosted Ollama server, through its native chat API.
+pub struct Ollama;
+
+#[derive(Serialize, Debug)]
+struct OllamaRequest {
+    model: String,
+    messages: Vec<Message>,
+    stream: bool,
+    options: OllamaOptions,
+}
+
+#[derive(Serialize, Debug)]
+struct OllamaOptions {
+    temperature: f32,
+    #[serde(flatten)]
+    extra: serde_json::Map<String, Value>,
+}
+
+/// A non-streamed reply: one message, with token counts under their own names.
+#[derive(Deserialize, Debug)]
+struct OllamaResponse {
+    message: Message,
+    #[serde(default)]
+    prompt_eval_count: Option<usize>,
+    #[serde(default)]
+    eval_count: Option<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OllamaErrorBody {
+    error: String,
+}
+
+impl Provider for Ollama {
+    async fn complete(&self, args: &Args, messages: Vec<Message>, params: Params) -> Result<Completion> {
+        // Ollama needs no key, but a proxy in front of it may
+        let api_key = env::var("OLLAMA_API_KEY").ok().filter(|key| !key.trim().is_empty());
+        let client = crate::http_client()?;
+
+        let request = OllamaRequest {
+            model: args.model.clone(),
+            messages,
+            stream: false,
+            options: OllamaOptions {
+                temperature: params.temperature,
+                extra: args.extra_params.iter().cloned().collect(),
+            },
+        };
+
+        let base_url = args.base_url.as_deref().unwrap_or(BASE_URL).trim_end_matches('/');
+        let url = format!("{}/api/chat", base_url);
+        let builder = super::with_optional_auth(client.post(url), args, api_key.as_deref().map(str::trim))?.json(&request);
+        let response = super::send_with_retries(args, builder, "Ollama API").await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = super::read_body(args, response).await.unwrap_or_else(|_| "Unknown error".to_string());
+            let error_text = match serde_json::from_str::<OllamaErrorBody>(&error_text) {
+                Ok(body) => body.error,
+                Err(_) => error_text,
+            };
+            return Err(super::provider_failure(args, format!("Ollama API request failed ({}): {}", status, error_text), status, &error_text));
+        }
+
+        let body = super::read_body(args, response).await?;
+        let response_body = serde_json::from_str::<OllamaResponse>(&body)
+            .context(GaiError::Provider("Failed to parse Ollama API response".to_string()))?;
+
+        let usage = match (response_body.prompt_eval_count, response_body.eval_count) {
+            (Some(prompt_tokens), Some(completion_tokens)) => Some(Usage { prompt_tokens, completion_tokens }),
+            _ => None,
+        };
+
+        Ok(Completion {
+            text: response_body.message.content,
+            usage,
+        })
+    }
+}
@@ -0,0 +1,293 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{Completion, Params, Provider, Usage};
+use crate::error::GaiError;
+use crate::{Api, Args, Message};
+
+/// Where and how to reach a provider that speaks the OpenAI chat completions API.
+pub struct OpenAICompatible {
+    pub name: &'static str,
+    pub base_url: &'static str,
+    pub api_key_var: &'static str,
+}
+
+pub const OPENAI: OpenAICompatible = OpenAICompatible {
+    name: "OpenAI API",
+    base_url: "https://api.openai.com/v1",
+    api_key_var: "OPENAI_API_KEY",
+};
+
+pub const DEEPSEEK: OpenAICompatible = OpenAICompatible {
+    name: "DeepSeek API",
+    base_url: "https://api.deepseek.com",
+    api_key_var: "DEEPSEEK_API_KEY",
+};
+
+pub const MISTRAL: OpenAICompatible = OpenAICompatible {
+    name: "Mistral API",
+    base_url: "https://api.mistral.ai/v1",
+    api_key_var: "MISTRAL_API_KEY",
+};
+
+#[derive(Serialize, Debug)]
+struct OpenAIRequest {
+    model: String,
+    messages: Vec<Message>,
+    temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<Value>,
+    #[serde(flatten)]
+    extra: serde_json::Map<String, Value>,
+}
+
+/// Request body for the Responses API, which takes `input` instead of
+/// `messages` and nests the response format under `text`.
+#[derive(Serialize, Debug)]
+struct ResponsesRequest {
+    model: String,
+    input: Vec<Message>,
+    temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<Value>,
+    #[serde(flatten)]
+    extra: serde_json::Map<String, Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAIResponse {
+    choices: Vec<Choice>,
+    #[serde(default)]
+    error: Option<OpenAIError>,
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Choice {
+    message: Message,
+}
+
+#[derive(Deserialize, Debug)]
+pub(super) struct OpenAIError {
+    pub message: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ResponsesResponse {
+    #[serde(default)]
+    output: Vec<OutputItem>,
+    #[serde(default)]
+    error: Option<OpenAIError>,
+    #[serde(default)]
+    usage: Option<ResponsesUsage>,
+}
+
+/// One item of a Responses API reply; reasoning items carry no content.
+#[derive(Deserialize, Debug)]
+struct OutputItem {
+    #[serde(default)]
+    content: Vec<OutputContent>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OutputContent {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ResponsesUsage {
+    input_tokens: usize,
+    output_tokens: usize,
+}
+
+/// Body of a non-2xx reply from an OpenAI-compatible API.
+#[derive(Deserialize, Debug)]
+pub(super) struct OpenAIErrorBody {
+    pub error: OpenAIError,
+}
+
+/// Reply from the `/models` endpoint of an OpenAI-compatible API.
+#[derive(Deserialize, Debug)]
+struct ModelList {
+    data: Vec<ModelInfo>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ModelInfo {
+    id: String,
+}
+
+impl Provider for OpenAICompatible {
+    async fn complete(&self, args: &Args, messages: Vec<Message>, params: Params) -> Result<Completion> {
+        let api_keys = super::read_api_keys(self.api_key_var)?;
+        self.send(args, &api_keys, messages, params).await
+    }
+}
+
+impl OpenAICompatible {
+    pub async fn send(&self, args: &Args, api_keys: &[String], messages: Vec<Message>, params: Params) -> Result<Completion> {
+        // Create API client
+        let client = crate::http_client()?;
+
+        // Create the request body
+        let path = match args.api {
+            Api::Chat => "chat/completions",
+            Api::Responses => "responses",
+        };
+        let url = format!("{}/{}", self.url(args), path);
+        let extra = args.extra_params.iter().cloned().collect::<serde_json::Map<_, _>>();
+        let body = match args.api {
+            Api::Chat => serde_json::to_value(OpenAIRequest {
+                model: args.model.clone(),
+                messages,
+                temperature: params.temperature,
+                response_format: params.response_format,
+                extra,
+            })?,
+            Api::Responses => serde_json::to_value(ResponsesRequest {
+                model: args.model.clone(),
+                input: messages,
+                temperature: params.temperature,
+                text: params.response_format.map(responses_format),
+                extra,
+            })?,
+        };
+
+        // Send request to the API, moving on to the next key when one is rate limited
+        let build = |api_key: &str| {
+            let mut builder = client.post(&url);
+            if args.provider == crate::Provider::OpenAI {
+                if let Some(org) = &args.openai_org {
+                    builder = builder.header("OpenAI-Organization", org);
+                }
+                if let Some(project) = &args.openai_project {
+                    builder = builder.header("OpenAI-Project", project);
+                }
+            }
+            Ok(super::with_headers(builder, args, api_key)?.json(&body))
+        };
+        let response = super::send_with_failover(args, api_keys, self.name, build).await?;
+
+        // Check if response status is successful
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = super::read_body(args, response).await.unwrap_or_else(|_| "Unknown error".to_string());
+            // Prefer the API's own explanation over the raw body
+            let error_text = match serde_json::from_str::<OpenAIErrorBody>(&error_text) {
+                Ok(body) => body.error.message,
+                Err(_) => error_text,
+            };
+            return Err(super::provider_failure(args, format!("{} request failed ({}): {}", self.name, status, error_text), status, &error_text));
+        }
+
+        let body = super::read_body(args, response).await?;
+        match args.api {
+            Api::Chat => self.parse_chat(&body),
+            Api::Responses => self.parse_responses(&body),
+        }
+    }
+
+    fn parse_chat(&self, body: &str) -> Result<Completion> {
+        let response_body = serde_json::from_str::<OpenAIResponse>(body)
+            .with_context(|| GaiError::Provider(format!("Failed to parse {} response", self.name)))?;
+
+        // Check for API errors
+        if let Some(error) = response_body.error {
+            return Err(GaiError::Provider(format!("{} error: {}", self.name, error.message)).into());
+        }
+
+        // Extract the reply from response
+        let reply = response_body.choices
+            .first()
+            .context(GaiError::Provider("No choices in response".to_string()))?
+            .message
+            .content
+            .clone();
+
+        Ok(Completion {
+            text: reply,
+            usage: response_body.usage,
+        })
+    }
+
+    fn parse_responses(&self, body: &str) -> Result<Completion> {
+        let response_body = serde_json::from_str::<ResponsesResponse>(body)
+            .with_context(|| GaiError::Provider(format!("Failed to parse {} response", self.name)))?;
+
+        if let Some(error) = response_body.error {
+            return Err(GaiError::Provider(format!("{} error: {}", self.name, error.message)).into());
+        }
+
+        // The reply is spread over the output_text parts of the message items
+        let text = response_body
+            .output
+            .into_iter()
+            .flat_map(|item| item.content)
+            .filter(|content| content.kind == "output_text")
+            .map(|content| content.text)
+            .collect::<String>();
+        if text.is_empty() {
+            return Err(GaiError::Provider("No output text in response".to_string()).into());
+        }
+
+        Ok(Completion {
+            text,
+            usage: response_body.usage.map(|usage| Usage {
+                prompt_tokens: usage.input_tokens,
+                completion_tokens: usage.output_tokens,
+            }),
+        })
+    }
+
+    /// Fail, suggesting the closest names, when `/models` doesn't list the model.
+    pub async fn check_model_listed(&self, args: &Args, api_key: &str) -> Result<()> {
+        let client = crate::http_client()?;
+        let url = format!("{}/models", self.url(args));
+        let response = super::send_with_retries(args, super::with_headers(client.get(url), args, api_key)?, self.name).await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(GaiError::Provider(format!("{} could not list models ({})", self.name, status)).into());
+        }
+
+        let models = response
+            .json::<ModelList>()
+            .await
+            .with_context(|| GaiError::Provider(format!("Failed to parse the {} model list", self.name)))?;
+
+        if models.data.iter().any(|model| model.id == args.model) {
+            return Ok(());
+        }
+
+        let mut ids = models.data.iter().map(|model| model.id.as_str()).collect::<Vec<_>>();
+        ids.sort_by_key(|id| crate::edit_distance(id, &args.model));
+        let suggestions = ids.iter().take(3).map(|id| format!("'{}'", id)).collect::<Vec<_>>();
+
+        let mut message = format!("Model '{}' not found for the {:?} provider", args.model, args.provider);
+        if !suggestions.is_empty() {
+            message.push_str(&format!("; did you mean {}?", suggestions.join(", ")));
+        }
+
+        Err(GaiError::Config(message).into())
+    }
+
+    /// The API root, honoring --base-url.
+    fn url<'a>(&'a self, args: &'a Args) -> &'a str {
+        args.base_url.as_deref().unwrap_or(self.base_url).trim_end_matches('/')
+    }
+}
+
+/// Move a chat completions `response_format` into the Responses API's
+/// `text.format`, which keeps the schema fields next to the type.
+fn responses_format(response_format: Value) -> Value {
+    let mut format = response_format.get("json_schema").cloned().unwrap_or_default();
+    if let Some(fields) = format.as_object_mut() {
+        fields.insert("type".to_string(), response_format["type"].clone());
+    }
+    serde_json::json!({ "format": format })
+}
@@ -0,0 +1,277 @@
+use anyhow::{Context, Result};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+use super::openai::OpenAIErrorBody;
+use super::{Completion, Params, Provider, Usage};
+use crate::error::GaiError;
+use crate::{Args, Message};
+
+const SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+const DEFAULT_TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+const DEFAULT_LOCATION: &str = "us-central1";
+
+/// Google issues service-account tokens for at most an hour.
+const TOKEN_LIFETIME_SECS: u64 = 3600;
+
+/// How long before it expires a token is replaced, so a long run such as
+/// --watch never sends one that runs out on the way.
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(300);
+
+/// The current token and when it expires.
+static ACCESS_TOKEN: Mutex<Option<(String, Instant)>> = Mutex::const_new(None);
+
+/// Gemini models on Vertex AI, authenticated with a service account.
+pub struct Vertex;
+
+/// The parts of a service-account key file needed to mint a token.
+#[derive(Deserialize)]
+struct ServiceAccount {
+    client_email: String,
+    private_key: String,
+    #[serde(default = "default_token_uri")]
+    token_uri: String,
+    #[serde(default)]
+    project_id: Option<String>,
+}
+
+fn default_token_uri() -> String {
+    DEFAULT_TOKEN_URI.to_string()
+}
+
+#[derive(Serialize)]
+struct Claims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: u64,
+    exp: u64,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    /// Seconds until the token expires
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct VertexRequest {
+    contents: Vec<VertexContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<VertexContent>,
+    generation_config: VertexGenerationConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct VertexContent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    role: Option<String>,
+    #[serde(default)]
+    parts: Vec<VertexPart>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct VertexPart {
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Serialize, Debug)]
+struct VertexGenerationConfig {
+    temperature: f32,
+    #[serde(flatten)]
+    extra: serde_json::Map<String, Value>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct VertexResponse {
+    #[serde(default)]
+    candidates: Vec<VertexCandidate>,
+    #[serde(default)]
+    usage_metadata: Option<VertexUsage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct VertexCandidate {
+    // Missing when the reply was blocked
+    #[serde(default)]
+    content: Option<VertexContent>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct VertexUsage {
+    prompt_token_count: usize,
+    #[serde(default)]
+    candidates_token_count: usize,
+}
+
+impl Provider for Vertex {
+    async fn complete(&self, args: &Args, messages: Vec<Message>, params: Params) -> Result<Completion> {
+        let account = service_account()?;
+        let project = project(&account)?;
+        let location = location();
+        let token = access_token(&account).await?;
+
+        // Gemini takes the system prompt separately and calls the assistant "model"
+        let mut system = Vec::new();
+        let mut contents = Vec::new();
+        for message in messages {
+            let part = VertexPart { text: message.content };
+            match message.role.as_str() {
+                "system" => system.push(part),
+                "assistant" => contents.push(VertexContent { role: Some("model".to_string()), parts: vec![part] }),
+                _ => contents.push(VertexContent { role: Some("user".to_string()), parts: vec![part] }),
+            }
+        }
+
+        let request = VertexRequest {
+            contents,
+            system_instruction: (!system.is_empty()).then_some(VertexContent { role: None, parts: system }),
+            generation_config: VertexGenerationConfig {
+                temperature: params.temperature,
+                extra: args.extra_params.iter().cloned().collect(),
+            },
+        };
+
+        // The global endpoint has no region in its host name
+        let host = match location.as_str() {
+            "global" => "https://aiplatform.googleapis.com".to_string(),
+            region => format!("https://{}-aiplatform.googleapis.com", region),
+        };
+        let base_url = args.base_url.as_deref().unwrap_or(&host).trim_end_matches('/');
+        let url = format!(
+            "{}/v1/projects/{}/locations/{}/publishers/google/models/{}:generateContent",
+            base_url, project, location, args.model
+        );
+
+        let builder = super::with_headers(crate::http_client()?.post(url), args, &token)?.json(&request);
+        let response = super::send_with_retries(args, builder, "Vertex AI API").await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = super::read_body(args, response).await.unwrap_or_else(|_| "Unknown error".to_string());
+            // Google errors share the {"error": {"message": ...}} shape
+            let error_text = match serde_json::from_str::<OpenAIErrorBody>(&error_text) {
+                Ok(body) => body.error.message,
+                Err(_) => error_text,
+            };
+            return Err(super::provider_failure(args, format!("Vertex AI API request failed ({}): {}", status, error_text), status, &error_text));
+        }
+
+        let body = super::read_body(args, response).await?;
+        let response_body = serde_json::from_str::<VertexResponse>(&body)
+            .context(GaiError::Provider("Failed to parse Vertex AI API response".to_string()))?;
+
+        let text = response_body
+            .candidates
+            .into_iter()
+            .next()
+            .and_then(|candidate| candidate.content)
+            .map(|content| content.parts.into_iter().map(|part| part.text).collect::<String>())
+            .filter(|text| !text.trim().is_empty())
+            .context(GaiError::Provider("Vertex AI returned no text; the reply may have been blocked".to_string()))?;
+
+        Ok(Completion {
+            text,
+            usage: response_body.usage_metadata.map(|usage| Usage {
+                prompt_tokens: usage.prompt_token_count,
+                completion_tokens: usage.candidates_token_count,
+            }),
+        })
+    }
+}
+
+/// Read the key file named by `GOOGLE_APPLICATION_CREDENTIALS`.
+fn service_account() -> Result<ServiceAccount> {
+    let path = env::var("GOOGLE_APPLICATION_CREDENTIALS").context(GaiError::Config(
+        "GOOGLE_APPLICATION_CREDENTIALS not found. Point it at a service-account JSON key file.".to_string(),
+    ))?;
+
+    let content = fs::read_to_string(&path)
+        .with_context(|| GaiError::Io(format!("Failed to read service-account key {}", path)))?;
+
+    serde_json::from_str(&content)
+        .with_context(|| GaiError::Config(format!("{} is not a valid service-account key file", path)))
+}
+
+/// The GCP project to bill, from `GOOGLE_CLOUD_PROJECT` or the key file.
+fn project(account: &ServiceAccount) -> Result<String> {
+    env::var("GOOGLE_CLOUD_PROJECT")
+        .ok()
+        .or_else(|| account.project_id.clone())
+        .ok_or_else(|| GaiError::Config("No GCP project found. Set GOOGLE_CLOUD_PROJECT.".to_string()).into())
+}
+
+/// The Vertex region, from `GOOGLE_CLOUD_LOCATION`.
+fn location() -> String {
+    env::var("GOOGLE_CLOUD_LOCATION").unwrap_or_else(|_| DEFAULT_LOCATION.to_string())
+}
+
+/// A bearer token for `account`, minted on first use and reused until it
+/// is about to expire.
+async fn access_token(account: &ServiceAccount) -> Result<String> {
+    // Held while minting, so concurrent requests share one new token
+    let mut cached = ACCESS_TOKEN.lock().await;
+    if let Some((token, expires)) = cached.as_ref()
+        && Instant::now() + TOKEN_REFRESH_MARGIN < *expires
+    {
+        return Ok(token.clone());
+    }
+
+    let (token, lifetime) = mint_token(account).await?;
+    *cached = Some((token.clone(), Instant::now() + lifetime));
+    Ok(token)
+}
+
+/// Trade a signed JWT for an access token (the OAuth 2.0 JWT bearer grant),
+/// returned with how long it lasts.
+async fn mint_token(account: &ServiceAccount) -> Result<(String, Duration)> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    let claims = Claims {
+        iss: &account.client_email,
+        scope: SCOPE,
+        aud: &account.token_uri,
+        iat: now,
+        exp: now + TOKEN_LIFETIME_SECS,
+    };
+
+    let key = EncodingKey::from_rsa_pem(account.private_key.as_bytes())
+        .context(GaiError::Config("The service-account private key is not a valid RSA key".to_string()))?;
+    let assertion = jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &key)
+        .context(GaiError::Config("Failed to sign the service-account token request".to_string()))?;
+
+    let response = crate::http_client()?
+        .post(&account.token_uri)
+        .form(&[("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"), ("assertion", &assertion)])
+        .send()
+        .await
+        .map_err(|e| crate::request_error(e, "Google OAuth"))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let error = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+        return Err(GaiError::Provider(format!("Google refused the service-account token request ({}): {}", status, error.trim())).into());
+    }
+
+    let token = response
+        .json::<TokenResponse>()
+        .await
+        .context(GaiError::Provider("Failed to parse the Google OAuth response".to_string()))?;
+
+    let lifetime = Duration::from_secs(token.expires_in.unwrap_or(TOKEN_LIFETIME_SECS));
+    Ok((token.access_token, lifetime))
+}
@@ -0,0 +1,174 @@
+/// Common misspellings and their corrections, lowercase.
+const MISSPELLINGS: &[(&str, &str)] = &[
+    ("accross", "across"),
+    ("acheive", "achieve"),
+    ("accomodate", "accommodate"),
+    ("adress", "address"),
+    ("adresses", "addresses"),
+    ("agian", "again"),
+    ("allready", "already"),
+    ("alreday", "already"),
+    ("apparant", "apparent"),
+    ("appearence", "appearance"),
+    ("arguement", "argument"),
+    ("arguements", "arguments"),
+    ("asyncronous", "asynchronous"),
+    ("availabe", "available"),
+    ("availible", "available"),
+    ("avaliable", "available"),
+    ("beacuse", "because"),
+    ("becasue", "because"),
+    ("becuase", "because"),
+    ("begining", "beginning"),
+    ("beleive", "believe"),
+    ("calender", "calendar"),
+    ("catagory", "category"),
+    ("chnage", "change"),
+    ("chnages", "changes"),
+    ("comand", "command"),
+    ("commited", "committed"),
+    ("commiting", "committing"),
+    ("compatability", "compatibility"),
+    ("compatable", "compatible"),
+    ("compatibilty", "compatibility"),
+    ("completly", "completely"),
+    ("configuraiton", "configuration"),
+    ("consistant", "consistent"),
+    ("contructor", "constructor"),
+    ("correclty", "correctly"),
+    ("currenly", "currently"),
+    ("deafult", "default"),
+    ("defualt", "default"),
+    ("definately", "definitely"),
+    ("dependancy", "dependency"),
+    ("dependancies", "dependencies"),
+    ("depricated", "deprecated"),
+    ("desciption", "description"),
+    ("diffrent", "different"),
+    ("dissapear", "disappear"),
+    ("enviroment", "environment"),
+    ("enviornment", "environment"),
+    ("equivalant", "equivalent"),
+    ("excecute", "execute"),
+    ("existant", "existent"),
+    ("explicitely", "explicitly"),
+    ("fucntion", "function"),
+    ("funtion", "function"),
+    ("gaurantee", "guarantee"),
+    ("happend", "happened"),
+    ("identifer", "identifier"),
+    ("immediatly", "immediately"),
+    ("implementaion", "implementation"),
+    ("implmentation", "implementation"),
+    ("independant", "independent"),
+    ("initalize", "initialize"),
+    ("intialize", "initialize"),
+    ("inteface", "interface"),
+    ("interupt", "interrupt"),
+    ("lenght", "length"),
+    ("libary", "library"),
+    ("maintainance", "maintenance"),
+    ("managment", "management"),
+    ("mesage", "message"),
+    ("messsage", "message"),
+    ("neccessary", "necessary"),
+    ("necesary", "necessary"),
+    ("noticable", "noticeable"),
+    ("occured", "occurred"),
+    ("occurence", "occurrence"),
+    ("ocurred", "occurred"),
+    ("paramter", "parameter"),
+    ("paramters", "parameters"),
+    ("perfomance", "performance"),
+    ("performace", "performance"),
+    ("persistant", "persistent"),
+    ("posible", "possible"),
+    ("prefered", "preferred"),
+    ("previos", "previous"),
+    ("priviledge", "privilege"),
+    ("proccess", "process"),
+    ("programatically", "programmatically"),
+    ("recieve", "receive"),
+    ("recieved", "received"),
+    ("recomend", "recommend"),
+    ("refered", "referred"),
+    ("refrence", "reference"),
+    ("relevent", "relevant"),
+    ("reponse", "response"),
+    ("repositry", "repository"),
+    ("requst", "request"),
+    ("retreive", "retrieve"),
+    ("seperate", "separate"),
+    ("seperately", "separately"),
+    ("seperator", "separator"),
+    ("succesful", "successful"),
+    ("successfull", "successful"),
+    ("sucessful", "successful"),
+    ("sucess", "success"),
+    ("suport", "support"),
+    ("supress", "suppress"),
+    ("teh", "the"),
+    ("threshhold", "threshold"),
+    ("transfered", "transferred"),
+    ("truely", "truly"),
+    ("unecessary", "unnecessary"),
+    ("unneccessary", "unnecessary"),
+    ("untill", "until"),
+    ("usefull", "useful"),
+    ("verison", "version"),
+    ("wich", "which"),
+    ("wierd", "weird"),
+    ("writting", "writing"),
+];
+
+/// Fix the misspellings in `text`, leaving alone anything in backticks,
+/// anything that looks like code (paths, `snake_case`, `camelCase`, calls)
+/// and words `is_known` accepts. Returns the corrected text and each
+/// (typo, correction) made.
+pub fn correct(text: &str, is_known: impl Fn(&str) -> bool) -> (String, Vec<(String, String)>) {
+    let mut corrected = String::with_capacity(text.len());
+    let mut fixes = Vec::new();
+    let mut in_code = false;
+
+    for piece in text.split_inclusive(char::is_whitespace) {
+        let token = piece.trim_end();
+        let was_in_code = in_code;
+        in_code ^= token.matches('`').count() % 2 == 1;
+
+        let word = token.trim_matches(|c: char| ",.;:!?\"'()".contains(c));
+        let plain = !word.is_empty()
+            && word.chars().all(char::is_alphabetic)
+            && !word.chars().skip(1).any(char::is_uppercase);
+        let correction = MISSPELLINGS
+            .iter()
+            .find(|(typo, _)| *typo == word.to_lowercase())
+            .map(|(_, correction)| *correction);
+
+        match correction {
+            Some(correction) if plain && !was_in_code && !token.contains('`') && !is_known(word) => {
+                let replacement = match word.chars().next() {
+                    Some(first) if first.is_uppercase() => first.to_uppercase().chain(correction.chars().skip(1)).collect(),
+                    _ => correction.to_string(),
+                };
+                corrected.push_str(&piece.replacen(word, &replacement, 1));
+                fixes.push((word.to_string(), replacement));
+            }
+            _ => corrected.push_str(piece),
+        }
+    }
+
+    (corrected, fixes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spelling_corrects_prose_but_not_code() {
+        let (corrected, fixes) = correct("fix: Recieve `recieve` seperate_ids, untill teh end.\n\nWich", |word| word.eq_ignore_ascii_case("wich"));
+
+        assert_eq!(corrected, "fix: Receive `recieve` seperate_ids, until the end.\n\nWich");
+        assert_eq!(fixes.len(), 3);
+    }
+}
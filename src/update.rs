@@ -0,0 +1,195 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::env;
+use std::fs;
+
+use crate::error::GaiError;
+
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/tsoodo/gitai/releases/latest";
+
+#[derive(Deserialize, Debug)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+    /// `sha256:<hex>`, which GitHub computes for every uploaded asset
+    #[serde(default)]
+    digest: Option<String>,
+}
+
+/// Extensions of archives, which would need unpacking to get at the binary.
+const ARCHIVE_EXTENSIONS: &[&str] = &[".tar.gz", ".tgz", ".tar.xz", ".tar.bz2", ".zip"];
+
+/// Extensions of checksum and signature files published next to a binary.
+const SIDECAR_EXTENSIONS: &[&str] = &[".sha256", ".sha256sum", ".sig", ".asc", ".minisig"];
+
+/// Names of files listing `<sha256>  <asset>` for a whole release.
+const CHECKSUM_FILES: &[&str] = &["SHA256SUMS", "SHA256SUMS.txt", "sha256sums.txt", "checksums.txt"];
+
+/// Replace the running binary with the latest GitHub release if it's newer.
+pub async fn self_update(check_only: bool) -> Result<()> {
+    let client = crate::http_client()?;
+    let current = env!("CARGO_PKG_VERSION");
+
+    let release = client
+        .get(LATEST_RELEASE_URL)
+        .header("User-Agent", "gitai")
+        .header("Accept", "application/vnd.github+json")
+        .send()
+        .await
+        .map_err(|e| crate::request_error(e, "GitHub API"))?
+        .error_for_status()
+        .context(GaiError::Provider("GitHub returned an error for the latest release".to_string()))?
+        .json::<Release>()
+        .await
+        .context(GaiError::Provider("Failed to parse GitHub release metadata".to_string()))?;
+
+    let latest = release.tag_name.trim_start_matches('v');
+    if parse_version(latest) <= parse_version(current) {
+        println!("✅ gitai {} is up to date", current);
+        return Ok(());
+    }
+
+    println!("⬆️ gitai {} is available (installed: {})", latest, current);
+    if check_only {
+        return Ok(());
+    }
+
+    // Release assets are expected to carry the OS and architecture in their name
+    let (os, arch) = (env::consts::OS, env::consts::ARCH);
+    let matching = release
+        .assets
+        .iter()
+        .filter(|asset| asset.name.contains(os) && asset.name.contains(arch))
+        .filter(|asset| !SIDECAR_EXTENSIONS.iter().any(|ext| asset.name.ends_with(ext)))
+        .collect::<Vec<_>>();
+    let is_archive = |asset: &&Asset| ARCHIVE_EXTENSIONS.iter().any(|ext| asset.name.ends_with(ext));
+
+    // Unpacking would take an archive library for each format, so only a bare binary will do
+    let asset = match (matching.iter().find(|asset| !is_archive(asset)), matching.first()) {
+        (Some(asset), _) => *asset,
+        (None, Some(archive)) => {
+            return Err(GaiError::Provider(format!(
+                "Release {} only has an archive for {}-{} ({}); download it from {} and install it by hand",
+                release.tag_name, os, arch, archive.name, archive.browser_download_url
+            )).into());
+        }
+        (None, None) => {
+            return Err(GaiError::Provider(format!("Release {} has no binary for {}-{}", release.tag_name, os, arch)).into());
+        }
+    };
+
+    let expected = published_sha256(&client, &release, asset).await?.ok_or_else(|| {
+        GaiError::Provider(format!("Release {} publishes no SHA-256 for {}, so it can't be verified; not installing it", release.tag_name, asset.name))
+    })?;
+
+    let bytes = download(&client, asset).await?;
+
+    let actual = ring::digest::digest(&ring::digest::SHA256, &bytes)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    if actual != expected {
+        return Err(GaiError::Provider(format!(
+            "The SHA-256 of the downloaded {} is {}, but the release says {}; not installing it",
+            asset.name, actual, expected
+        )).into());
+    }
+
+    let exe = env::current_exe().context(GaiError::Io("Failed to locate the gitai executable".to_string()))?;
+    let staged = exe.with_extension("new");
+    fs::write(&staged, &bytes).with_context(|| GaiError::Io(format!("Failed to write {}", staged.display())))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))
+            .context(GaiError::Io("Failed to make the new binary executable".to_string()))?;
+    }
+
+    // Windows can't overwrite a running executable, but it can rename it
+    if cfg!(windows) {
+        let old = exe.with_extension("old");
+        let _ = fs::remove_file(&old);
+        fs::rename(&exe, &old).with_context(|| GaiError::Io(format!("Failed to move {} aside", exe.display())))?;
+    }
+
+    fs::rename(&staged, &exe).with_context(|| GaiError::Io(format!("Failed to replace {}", exe.display())))?;
+
+    println!("✅ Updated gitai to {}", latest);
+    Ok(())
+}
+
+async fn download(client: &reqwest::Client, asset: &Asset) -> Result<Vec<u8>> {
+    let bytes = client
+        .get(&asset.browser_download_url)
+        .header("User-Agent", "gitai")
+        .send()
+        .await
+        .context(GaiError::Provider(format!("Failed to download {}", asset.name)))?
+        .error_for_status()
+        .context(GaiError::Provider(format!("GitHub returned an error downloading {}", asset.name)))?
+        .bytes()
+        .await
+        .context(GaiError::Provider(format!("Failed to download {}", asset.name)))?;
+
+    Ok(bytes.to_vec())
+}
+
+/// The lowercase hex SHA-256 the release publishes for `asset`: GitHub's
+/// own digest, else a `<asset>.sha256` file, else a checksum list for the
+/// whole release.
+async fn published_sha256(client: &reqwest::Client, release: &Release, asset: &Asset) -> Result<Option<String>> {
+    if let Some(hex) = asset.digest.as_deref().and_then(|digest| digest.strip_prefix("sha256:")) {
+        return Ok(Some(hex.to_lowercase()));
+    }
+
+    let sidecar = format!("{}.sha256", asset.name);
+    if let Some(file) = release.assets.iter().find(|candidate| candidate.name == sidecar) {
+        let content = String::from_utf8_lossy(&download(client, file).await?).to_string();
+        return Ok(content.split_whitespace().next().map(str::to_lowercase));
+    }
+
+    if let Some(file) = release.assets.iter().find(|candidate| CHECKSUM_FILES.contains(&candidate.name.as_str())) {
+        let content = String::from_utf8_lossy(&download(client, file).await?).to_string();
+        return Ok(checksum_for(&content, &asset.name));
+    }
+
+    Ok(None)
+}
+
+/// The checksum listed for `name` in `sha256sum` output; binary-mode
+/// entries mark the name with a `*`.
+fn checksum_for(list: &str, name: &str) -> Option<String> {
+    list.lines().find_map(|line| {
+        let (hex, file) = line.split_once(char::is_whitespace)?;
+        (file.trim().trim_start_matches('*') == name).then(|| hex.to_lowercase())
+    })
+}
+
+fn parse_version(version: &str) -> Vec<u64> {
+    version
+        .split(['.', '-', '+'])
+        .map_while(|part| part.parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_for_reads_sha256sum_output() {
+        let list = "AB12  gitai-linux-x86_64\ncd34 *gitai-macos-aarch64\n";
+
+        assert_eq!(checksum_for(list, "gitai-linux-x86_64").as_deref(), Some("ab12"));
+        assert_eq!(checksum_for(list, "gitai-macos-aarch64").as_deref(), Some("cd34"));
+        assert_eq!(checksum_for(list, "gitai-windows-x86_64.exe"), None);
+    }
+}